@@ -1,5 +1,8 @@
-use actix_web::HttpMessage;
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use actix_web::{HttpMessage, HttpRequest};
+use async_std::task;
+use dashmap::DashMap;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use lazy_static::lazy_static;
 use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
 use std::{
@@ -12,9 +15,16 @@ use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
 use futures_util::future::LocalBoxFuture;
 
 use crate::{
+    constants::{ACCESS_TOKEN_LIFETIME, SESSION_ACTIVITY_INTERVAL},
+    database::{
+        machine_client::{self, MachineScope},
+        session::{self, Session},
+        user,
+    },
     environment::JWT_SECRET,
     errors::{Error, Result},
-    utilities::get_time_millis,
+    signing,
+    utilities::{get_time_millis, get_time_secs},
 };
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -22,12 +32,64 @@ pub struct UserJwt {
     pub(crate) id: String,
     pub(crate) issued_at: u128,
     pub(crate) expires_at: u128,
+    // absent on tokens issued before refresh tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) session_id: Option<String>,
+}
+
+// access tokens issued to machine clients through the client_credentials grant
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ClientJwt {
+    pub(crate) client_id: String,
+    pub(crate) scopes: Vec<MachineScope>,
+    pub(crate) issued_at: u128,
+    pub(crate) expires_at: u128,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Claims {
+    User(UserJwt),
+    Client(ClientJwt),
+    // signed with JWT_SECRET, so never trusted without their session
+    #[serde(skip)]
+    Legacy(UserJwt),
 }
 
 #[derive(Clone, Debug)]
 pub struct Authenticate {
     pub jwt: String,
     pub jwt_content: UserJwt,
+    pub session_id: String,
+}
+
+// service routes take ReqData<Result<MachineAuthenticate>> instead of Authenticate
+#[derive(Clone, Debug)]
+pub struct MachineAuthenticate {
+    pub client_id: String,
+    pub scopes: Vec<MachineScope>,
+}
+
+impl MachineAuthenticate {
+    pub fn require(&self, scope: MachineScope) -> Result<()> {
+        if self.scopes.contains(&scope) {
+            Ok(())
+        } else {
+            Err(Error::MissingPermission)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SessionTokens {
+    pub session_id: String,
+    pub token: String,
+    pub refresh_token: String,
+}
+
+lazy_static! {
+    // session id -> last time activity was written, to throttle writes
+    pub static ref LAST_ACTIVITY: DashMap<String, u64> = DashMap::new();
 }
 
 pub struct JwtAuthentication;
@@ -54,42 +116,172 @@ pub struct JwtMiddleware<S> {
     service: Rc<S>,
 }
 
-pub async fn validate_token(jwt: &String) -> Result<Authenticate> {
-    let mut validation = Validation::new(Algorithm::HS256);
+async fn decode_claims(jwt: &str) -> Result<Claims> {
+    let header = decode_header(jwt)?;
+    let mut validation = Validation::new(header.alg);
     validation.required_spec_claims = HashSet::new();
     validation.validate_exp = false;
-    let token_data = decode::<UserJwt>(
-        jwt,
-        &DecodingKey::from_secret(JWT_SECRET.as_ref()),
-        &validation,
-    )?;
+    if header.alg == Algorithm::HS256 {
+        // tokens issued before asymmetric signing
+        let Some(secret) = &*JWT_SECRET else {
+            return Err(Error::InvalidToken);
+        };
+        let claims =
+            decode::<UserJwt>(jwt, &DecodingKey::from_secret(secret.as_ref()), &validation)?.claims;
+        Ok(Claims::Legacy(claims))
+    } else {
+        signing::decode::<Claims>(jwt, validation).await
+    }
+}
+
+// only accepts user access tokens
+pub async fn validate_token(jwt: &str) -> Result<Authenticate> {
+    match decode_claims(jwt).await? {
+        Claims::User(claims) => validate_user(jwt, claims, false).await,
+        Claims::Legacy(claims) => validate_user(jwt, claims, true).await,
+        Claims::Client(_) => Err(Error::InvalidToken),
+    }
+}
 
+async fn validate_user(jwt: &str, claims: UserJwt, legacy: bool) -> Result<Authenticate> {
     let millis = get_time_millis();
-    if millis > token_data.claims.expires_at {
+    if millis > claims.expires_at {
         return Err(Error::InvalidToken);
     }
-    let collection = crate::database::session::get_collection();
-    let query = collection
+    // access tokens are short-lived, so their session is not checked against the database;
+    // anything else must still match a session, which revocation removes
+    let stateless =
+        !legacy && claims.expires_at.saturating_sub(claims.issued_at) <= ACCESS_TOKEN_LIFETIME;
+    let session_id = match claims.session_id.clone().filter(|_| stateless) {
+        Some(session_id) => session_id,
+        None => {
+            // long-lived tokens issued before refresh tokens
+            let collection = session::get_collection();
+            let session = collection
+                .find_one(doc! {
+                    "token": jwt
+                })
+                .await?
+                .ok_or(Error::InvalidToken)?;
+            session.id
+        }
+    };
+    // a suspension takes effect immediately rather than once the access token expires
+    user::get_collection()
         .find_one(doc! {
-            "token": jwt
+            "id": &claims.id
         })
-        .await?;
-    if query.is_some() {
-        return Ok(Authenticate {
-            jwt: jwt.to_string(),
-            jwt_content: token_data.claims,
-        });
+        .await?
+        .ok_or(Error::InvalidToken)?
+        .ensure_active()?;
+    Ok(Authenticate {
+        jwt: jwt.to_string(),
+        jwt_content: claims,
+        session_id,
+    })
+}
+
+async fn validate_client(claims: ClientJwt) -> Result<MachineAuthenticate> {
+    if get_time_millis() > claims.expires_at {
+        return Err(Error::InvalidToken);
     }
-    Err(Error::InvalidToken)
+    // deleting a client, or taking scopes away from it, takes effect immediately
+    let client = machine_client::get_collection()
+        .find_one(doc! {
+            "id": &claims.client_id
+        })
+        .await?
+        .ok_or(Error::InvalidToken)?;
+    let scopes = claims
+        .scopes
+        .into_iter()
+        .filter(|scope| client.scopes.contains(scope))
+        .collect();
+    Ok(MachineAuthenticate {
+        client_id: client.id,
+        scopes,
+    })
 }
 
-pub async fn get_token(req: &ServiceRequest) -> Result<Authenticate> {
+pub async fn create_access_token(user_id: &str, session_id: &str) -> Result<String> {
+    let millis = get_time_millis();
+    signing::encode(&UserJwt {
+        id: user_id.to_string(),
+        issued_at: millis,
+        expires_at: millis + ACCESS_TOKEN_LIFETIME,
+        session_id: Some(session_id.to_string()),
+    })
+    .await
+}
+
+pub async fn create_session(
+    user_id: String,
+    friendly_name: String,
+    persistent: bool,
+    req: &HttpRequest,
+) -> Result<SessionTokens> {
+    let (session, refresh_token) = Session::new(user_id, friendly_name, persistent, req);
+    let token = create_access_token(&session.user_id, &session.id).await?;
+    let session_id = session.id.clone();
+    session::get_collection().insert_one(session).await?;
+    Ok(SessionTokens {
+        session_id,
+        token,
+        refresh_token,
+    })
+}
+
+fn get_bearer(req: &ServiceRequest) -> Result<String> {
     let authorization = req
         .headers()
         .get("Authorization")
         .ok_or(Error::MissingToken)?;
     let jwt = &authorization.to_str().map_err(|_| Error::InvalidToken)?[7..];
-    validate_token(&jwt.to_string()).await
+    Ok(jwt.to_string())
+}
+
+fn touch_session(req: &ServiceRequest, authenticate: &Authenticate) {
+    let now = get_time_secs();
+    let stale = LAST_ACTIVITY
+        .get(&authenticate.session_id)
+        .is_none_or(|t| now.saturating_sub(*t) >= SESSION_ACTIVITY_INTERVAL);
+    if stale {
+        LAST_ACTIVITY.insert(authenticate.session_id.clone(), now);
+        let ip = req
+            .connection_info()
+            .realip_remote_addr()
+            .map(|ip| ip.to_string());
+        task::spawn(session::touch(authenticate.session_id.clone(), ip));
+    }
+}
+
+// users and machine clients both send a bearer token, told apart by its claims
+pub async fn get_token(
+    req: &ServiceRequest,
+) -> (Result<Authenticate>, Result<MachineAuthenticate>) {
+    let jwt = match get_bearer(req) {
+        Ok(jwt) => jwt,
+        Err(e) => return (Err(e.clone()), Err(e)),
+    };
+    match decode_claims(&jwt).await {
+        Ok(Claims::User(claims)) => user_token(req, &jwt, claims, false).await,
+        Ok(Claims::Legacy(claims)) => user_token(req, &jwt, claims, true).await,
+        Ok(Claims::Client(claims)) => (Err(Error::MissingToken), validate_client(claims).await),
+        Err(e) => (Err(e.clone()), Err(e)),
+    }
+}
+
+async fn user_token(
+    req: &ServiceRequest,
+    jwt: &str,
+    claims: UserJwt,
+    legacy: bool,
+) -> (Result<Authenticate>, Result<MachineAuthenticate>) {
+    let authenticate = validate_user(jwt, claims, legacy).await;
+    if let Ok(authenticate) = &authenticate {
+        touch_session(req, authenticate);
+    }
+    (authenticate, Err(Error::MissingToken))
 }
 
 impl<S, B> Service<ServiceRequest> for JwtMiddleware<S>
@@ -113,8 +305,9 @@ where
     fn call(self: &JwtMiddleware<S>, req: ServiceRequest) -> Self::Future {
         let svc = self.service.clone();
         Box::pin(async move {
-            let token = get_token(&req).await;
-            req.extensions_mut().insert(token);
+            let (user, machine) = get_token(&req).await;
+            req.extensions_mut().insert(user);
+            req.extensions_mut().insert(machine);
             svc.call(req).await
         })
     }
@@ -1,49 +1,60 @@
+use log::{error, info};
+use mongodb::bson::doc;
+
 use crate::{
-    constants::CONTINUE_TIMEOUT,
-    routes::{forgot, login, mfa, register, update_password},
+    authenticate::LAST_ACTIVITY,
+    constants::{
+        EMAIL_FAILED_RETENTION, EMAIL_SENT_RETENTION, SESSION_ACTIVITY_INTERVAL,
+        WEBHOOK_DELIVERY_RETENTION,
+    },
+    database::{email_outbox, session, webhook_delivery},
+    deletion, flows,
     utilities::get_time_secs,
 };
 
-pub fn run() {
-    let now = get_time_secs();
-    for pending in login::PENDING_LOGINS.iter() {
-        if now - pending.value().time > CONTINUE_TIMEOUT {
-            login::PENDING_LOGINS.remove(pending.key());
-        }
-    }
-    for pending in login::PENDING_MFAS.iter() {
-        if now - pending.value().time > CONTINUE_TIMEOUT {
-            login::PENDING_MFAS.remove(pending.key());
-        }
-    }
-    for pending in register::PENDING_REGISTERS1.iter() {
-        if now - pending.value().time > CONTINUE_TIMEOUT {
-            register::PENDING_REGISTERS1.remove(pending.key());
-        }
-    }
-    for pending in register::PENDING_REGISTERS2.iter() {
-        if now - pending.value().time > CONTINUE_TIMEOUT {
-            register::PENDING_REGISTERS2.remove(pending.key());
-        }
-    }
-    for pending in mfa::PENDING_MFA_SETUPS.iter() {
-        if now - pending.value().time > CONTINUE_TIMEOUT {
-            mfa::PENDING_MFA_SETUPS.remove(pending.key());
-        }
-    }
-    for pending in forgot::PENDING_FORGOTS1.iter() {
-        if now - pending.value().time > CONTINUE_TIMEOUT {
-            forgot::PENDING_FORGOTS1.remove(pending.key());
-        }
-    }
-    for pending in forgot::PENDING_FORGOTS2.iter() {
-        if now - pending.value().time > CONTINUE_TIMEOUT {
-            forgot::PENDING_FORGOTS2.remove(pending.key());
-        }
-    }
-    for pending in update_password::PENDING_UPDATES.iter() {
-        if now - pending.value().time > CONTINUE_TIMEOUT {
-            update_password::PENDING_UPDATES.remove(pending.key());
-        }
+pub async fn run() {
+    match flows::purge_expired().await {
+        Ok(0) => {}
+        Ok(count) => info!("Removed {} expired flows", count),
+        Err(e) => error!("Failed to purge expired flows: {:?}", e),
     }
+    let now = get_time_secs();
+    LAST_ACTIVITY.retain(|_, time| now.saturating_sub(*time) < SESSION_ACTIVITY_INTERVAL);
+    // sessions created before refresh tokens have no expiry
+    let result = session::get_collection()
+        .delete_many(doc! {
+            "expires_at": { "$gt": 0, "$lte": now as i64 }
+        })
+        .await;
+    if let Err(e) = result {
+        error!("Failed to remove expired sessions: {}", e);
+    }
+    // delivered emails hold reset links and codes, so they aren't kept for long
+    let result = email_outbox::get_collection()
+        .delete_many(doc! {
+            "$or": [
+                {
+                    "status": "sent",
+                    "updated_at": { "$lte": now.saturating_sub(EMAIL_SENT_RETENTION) as i64 }
+                },
+                {
+                    "status": "failed",
+                    "updated_at": { "$lte": now.saturating_sub(EMAIL_FAILED_RETENTION) as i64 }
+                }
+            ]
+        })
+        .await;
+    if let Err(e) = result {
+        error!("Failed to remove old emails: {}", e);
+    }
+    let result = webhook_delivery::get_collection()
+        .delete_many(doc! {
+            "status": { "$in": ["sent", "failed"] },
+            "updated_at": { "$lte": now.saturating_sub(WEBHOOK_DELIVERY_RETENTION) as i64 }
+        })
+        .await;
+    if let Err(e) = result {
+        error!("Failed to remove old webhook deliveries: {}", e);
+    }
+    deletion::purge_due().await;
 }
@@ -3,6 +3,67 @@ pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 pub const SHORT_SESSION: u128 = 604800000; // 7 days
 pub const LONG_SESSION: u128 = 2592000000; // 30 days
+pub const ACCESS_TOKEN_LIFETIME: u128 = 300000; // 5 minutes
+pub const REFRESH_TOKEN_HISTORY: i32 = 20;
 pub const ELEVATED_SESSION: u128 = 300000; // 5 minutes
+pub const SESSION_ACTIVITY_INTERVAL: u64 = 300; // 5 minutes
 
 pub const CONTINUE_TIMEOUT: u64 = 3600; // 1 hour
+pub const SHORT_CONTINUE_TIMEOUT: u64 = 600; // 10 minutes
+pub const LOCKDOWN_TIMEOUT: u64 = 604800; // 7 days
+pub const CAPTCHA_CHALLENGE_TIMEOUT: u64 = 600; // 10 minutes
+
+pub const EMAIL_OUTBOX_INTERVAL: u64 = 5; // 5 seconds
+pub const EMAIL_SEND_TIMEOUT: u64 = 300; // 5 minutes
+pub const EMAIL_MAX_ATTEMPTS: u32 = 8;
+pub const EMAIL_RETRY_BASE: u64 = 30; // 30 seconds, doubled after each attempt
+pub const EMAIL_RETRY_MAX: u64 = 3600; // 1 hour
+pub const EMAIL_SENT_RETENTION: u64 = 604800; // 7 days
+pub const EMAIL_FAILED_RETENTION: u64 = 2592000; // 30 days
+pub const EMAIL_OUTBOX_FAILURES: i64 = 50;
+
+pub const WEBHOOK_INTERVAL: u64 = 5; // 5 seconds
+pub const WEBHOOK_TIMEOUT: u64 = 10; // 10 seconds
+pub const WEBHOOK_SEND_TIMEOUT: u64 = 60; // 1 minute
+pub const WEBHOOK_MAX_ATTEMPTS: u32 = 12;
+pub const WEBHOOK_RETRY_BASE: u64 = 30; // 30 seconds, doubled after each attempt
+pub const WEBHOOK_RETRY_MAX: u64 = 21600; // 6 hours
+pub const WEBHOOK_DELIVERY_RETENTION: u64 = 2592000; // 30 days
+pub const WEBHOOK_DELIVERY_PAGE_SIZE: i64 = 50;
+
+pub const FAILURE_WINDOW: u64 = 3600; // 1 hour
+pub const LOGIN_CAPTCHA_EMAIL_THRESHOLD: u64 = 3;
+pub const LOGIN_CAPTCHA_IP_THRESHOLD: u64 = 10;
+pub const ACCOUNT_FAILURE_WINDOW: u64 = 86400; // 1 day
+pub const ACCOUNT_BACKOFF_THRESHOLD: u64 = 3;
+pub const ACCOUNT_BACKOFF_BASE: u64 = 2; // 2 seconds, doubled after each failure
+pub const ACCOUNT_BACKOFF_MAX: u64 = 900; // 15 minutes
+pub const ACCOUNT_LOCKOUT_THRESHOLD: u64 = 10;
+pub const ACCOUNT_LOCKOUT_DURATION: u64 = 3600; // 1 hour
+pub const MFA_TOKEN_MAX_ATTEMPTS: u64 = 5;
+pub const UNLOCK_TIMEOUT: u64 = 86400; // 1 day
+
+pub const EXPORT_LIFETIME: u64 = 604800; // 7 days
+pub const EXPORT_CHUNK_SIZE: usize = 4194304; // 4 MiB
+
+pub const RECOVERY_CODE_ITERATIONS: u32 = 100000;
+pub const RECOVERY_CODES_WARNING: u64 = 3;
+
+pub const ADMIN_SEARCH_LIMIT: i64 = 50;
+pub const AUDIT_PAGE_SIZE: i64 = 50;
+pub const AUDIT_PAGE_MAX: i64 = 200;
+
+pub const AVATAR_MAX_SIZE: isize = 8388608; // 8 MiB
+pub const AVATAR_MAX_DIMENSION: isize = 4096;
+
+pub const AUTHORIZATION_CODE_TIMEOUT: u64 = 600; // 10 minutes
+pub const OAUTH_TOKEN_LIFETIME: u64 = 3600; // 1 hour
+pub const MACHINE_TOKEN_LIFETIME: u64 = 3600; // 1 hour
+pub const CLIENT_ASSERTION_MAX_AGE: u64 = 300; // 5 minutes
+pub const USER_LOOKUP_MAX: usize = 100;
+
+pub const SIGNING_KEY_ROTATION: u64 = 2592000; // 30 days
+pub const SIGNING_KEY_OVERLAP: u64 = (LONG_SESSION / 1000) as u64; // outlives any session
+pub const SIGNING_KEY_CACHE_TIMEOUT: u64 = 60; // 1 minute
+pub const SIGNING_KEY_PUBLISH_AHEAD: u64 = 86400; // 1 day
+pub const SIGNING_KEY_RELOAD_INTERVAL: u64 = 5; // 5 seconds
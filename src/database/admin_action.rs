@@ -0,0 +1,81 @@
+use log::error;
+use mongodb::Collection;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::utilities::get_time_secs;
+
+static COLLECTION: OnceCell<Collection<AdminAction>> = OnceCell::new();
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AdminAction {
+    pub id: String,
+    pub admin_id: String,
+    pub action: AdminActionKind,
+    // absent for actions that don't target a single user, e.g. searches
+    pub target_user_id: Option<String>,
+    pub detail: Option<String>,
+    pub created_at: u64,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminActionKind {
+    SearchUsers,
+    ViewSessions,
+    ViewPasskeys,
+    ForceLogout,
+    UpdateStatus,
+    ResetMfa,
+    SendPasswordReset,
+    ViewAuditEvents,
+    ViewEmailOutbox,
+    ViewFlowMetrics,
+    ViewWebhooks,
+    CreateWebhook,
+    DeleteWebhook,
+    ViewWebhookDeliveries,
+    ViewMachineClients,
+    CreateMachineClient,
+    DeleteMachineClient,
+}
+
+pub fn get_collection() -> Collection<AdminAction> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<AdminAction>("admin_actions");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
+
+// called once the change has been made, so a failure is logged rather than
+// failing a request whose change can't be undone
+pub async fn record(
+    admin_id: &str,
+    action: AdminActionKind,
+    target_user_id: Option<&str>,
+    detail: Option<String>,
+) {
+    let result = get_collection()
+        .insert_one(AdminAction {
+            id: Ulid::new().to_string(),
+            admin_id: admin_id.to_string(),
+            action,
+            target_user_id: target_user_id.map(|id| id.to_string()),
+            detail,
+            created_at: get_time_secs(),
+        })
+        .await;
+    if let Err(e) = result {
+        error!(
+            "Failed to record {:?} admin action by {}: {}",
+            action, admin_id, e
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use actix_web::HttpRequest;
+use futures_util::StreamExt;
+use log::error;
+use mongodb::{
+    bson::{doc, Document},
+    options::IndexOptions,
+    Collection, IndexModel,
+};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use super::session::{get_ip, parse_user_agent, UserAgent};
+use crate::{errors::Result, utilities::get_time_secs};
+
+static COLLECTION: OnceCell<Collection<AuditEvent>> = OnceCell::new();
+
+// append-only; events are never updated or deleted
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuditEvent {
+    // ULID, so sorting by id sorts by time; also used as the pagination cursor
+    pub id: String,
+    pub event: AuditEventKind,
+    // the user who performed the action; none when unauthenticated, e.g. a failed login
+    pub actor_id: Option<String>,
+    // the user the event concerns
+    pub target_id: String,
+    pub session_id: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<UserAgent>,
+    pub detail: Option<String>,
+    pub created_at: u64,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEventKind {
+    Login,
+    LoginFailed,
+    Escalated,
+    MfaEnabled,
+    MfaDisabled,
+    PasskeyRegistered,
+    PasskeyDeleted,
+    PasswordChanged,
+    PasswordReset,
+    SessionsRevoked,
+    StatusChanged,
+    Lockdown,
+    SignInLocked,
+    SignInUnlocked,
+    DeletionScheduled,
+    DeletionCancelled,
+    AccountDeleted,
+    DataExported,
+}
+
+pub fn get_collection() -> Collection<AuditEvent> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<AuditEvent>("audit_events");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
+
+pub async fn create_indexes() -> Result<()> {
+    let collection = get_collection();
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "id": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "target_id": 1, "id": -1 })
+                .build(),
+        )
+        .await?;
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "actor_id": 1, "id": -1 })
+                .build(),
+        )
+        .await?;
+    Ok(())
+}
+
+// called once the change has been made, so a failure is logged rather than
+// failing a request whose change can't be undone
+pub async fn record(
+    req: &HttpRequest,
+    event: AuditEventKind,
+    actor_id: Option<&str>,
+    target_id: &str,
+    session_id: Option<&str>,
+    detail: Option<String>,
+) {
+    let user_agent = req
+        .headers()
+        .get("User-Agent")
+        .and_then(|ua| ua.to_str().ok())
+        .and_then(parse_user_agent);
+    insert(AuditEvent {
+        id: Ulid::new().to_string(),
+        event,
+        actor_id: actor_id.map(|id| id.to_string()),
+        target_id: target_id.to_string(),
+        session_id: session_id.map(|id| id.to_string()),
+        ip: get_ip(req),
+        user_agent,
+        detail,
+        created_at: get_time_secs(),
+    })
+    .await
+}
+
+// for events that don't come from a request, e.g. background jobs
+pub async fn record_system(event: AuditEventKind, target_id: &str, detail: Option<String>) {
+    insert(AuditEvent {
+        id: Ulid::new().to_string(),
+        event,
+        actor_id: None,
+        target_id: target_id.to_string(),
+        session_id: None,
+        ip: None,
+        user_agent: None,
+        detail,
+        created_at: get_time_secs(),
+    })
+    .await
+}
+
+async fn insert(event: AuditEvent) {
+    if let Err(e) = get_collection().insert_one(&event).await {
+        error!(
+            "Failed to record {:?} audit event for user {}: {}",
+            event.event, event.target_id, e
+        );
+    }
+}
+
+// returns up to `limit` events older than the `before` cursor, newest first,
+// along with the cursor for the next page if there is one
+pub async fn query(
+    mut filter: Document,
+    before: Option<String>,
+    limit: i64,
+) -> Result<(Vec<AuditEvent>, Option<String>)> {
+    if let Some(before) = before {
+        filter.insert("id", doc! { "$lt": before });
+    }
+    let mut events = get_collection()
+        .find(filter)
+        .sort(doc! { "id": -1 })
+        .limit(limit + 1)
+        .await?
+        .collect::<Vec<std::result::Result<AuditEvent, _>>>()
+        .await
+        .into_iter()
+        .collect::<std::result::Result<Vec<AuditEvent>, _>>()?;
+    let next_cursor = if events.len() as i64 > limit {
+        events.truncate(limit as usize);
+        events.last().map(|event| event.id.clone())
+    } else {
+        None
+    };
+    Ok((events, next_cursor))
+}
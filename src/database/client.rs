@@ -0,0 +1,28 @@
+use mongodb::Collection;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+static COLLECTION: OnceCell<Collection<Client>> = OnceCell::new();
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Client {
+    pub id: String,
+    // SHA-256 hash of the client secret; public clients have none and must use PKCE
+    pub secret: Option<String>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub created_by: String,
+}
+
+pub fn get_collection() -> Collection<Client> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<Client>("clients");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
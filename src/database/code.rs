@@ -1,11 +1,20 @@
+use std::num::NonZeroU32;
+
+use async_std::task;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_util::TryStreamExt;
 use mongodb::{bson::doc, Collection};
 use once_cell::sync::OnceCell;
+use ring::pbkdf2;
 use serde::{Deserialize, Serialize};
 
+use crate::{constants::RECOVERY_CODE_ITERATIONS, errors::Result, utilities::random_number};
+
 static COLLECTION: OnceCell<Collection<Code>> = OnceCell::new();
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Code {
+    // hashed with hash_code
     pub code: String,
     pub user_id: String,
 }
@@ -22,3 +31,99 @@ pub fn get_collection() -> Collection<Code> {
         c
     }
 }
+
+const HASH_PREFIX: &str = "pbkdf2-sha256";
+
+// codes only have 8 digits, so each gets its own salt and a slow hash to make a
+// leaked collection expensive to reverse
+pub fn hash_code(code: &str) -> String {
+    let salt = random_number(16);
+    let mut hash = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(RECOVERY_CODE_ITERATIONS).expect("iterations must be non-zero"),
+        &salt,
+        code.as_bytes(),
+        &mut hash,
+    );
+    format!(
+        "{}${}${}${}",
+        HASH_PREFIX,
+        RECOVERY_CODE_ITERATIONS,
+        STANDARD.encode(salt),
+        STANDARD.encode(hash)
+    )
+}
+
+// hashed off the async executor, as each hash is deliberately slow
+pub async fn hash_codes(codes: &[String]) -> Vec<String> {
+    let codes = codes.to_vec();
+    task::spawn_blocking(move || codes.iter().map(|code| hash_code(code)).collect()).await
+}
+
+fn verify_code(stored: &str, code: &str) -> bool {
+    let mut parts = stored.split('$');
+    if parts.next() != Some(HASH_PREFIX) {
+        return false;
+    }
+    let (Some(iterations), Some(salt), Some(hash)) = (
+        parts
+            .next()
+            .and_then(|iterations| iterations.parse::<u32>().ok())
+            .and_then(NonZeroU32::new),
+        parts.next().and_then(|salt| STANDARD.decode(salt).ok()),
+        parts.next().and_then(|hash| STANDARD.decode(hash).ok()),
+    ) else {
+        return false;
+    };
+    pbkdf2::verify(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        &salt,
+        code.as_bytes(),
+        &hash,
+    )
+    .is_ok()
+}
+
+// removes the matching code, so each can only be used once
+pub async fn redeem(user_id: &str, code: &str) -> Result<bool> {
+    let collection = get_collection();
+    let stored = collection
+        .find(doc! {
+            "user_id": user_id
+        })
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    let code = code.to_string();
+    let matched = task::spawn_blocking(move || {
+        stored
+            .into_iter()
+            .find(|stored| verify_code(&stored.code, &code))
+    })
+    .await;
+    let Some(matched) = matched else {
+        return Ok(false);
+    };
+    let result = collection
+        .delete_one(doc! {
+            "user_id": &matched.user_id,
+            "code": &matched.code
+        })
+        .await?;
+    Ok(result.deleted_count > 0)
+}
+
+// invalidates any existing recovery codes for the user; `codes` are already hashed
+pub async fn replace_codes(user_id: &str, codes: Vec<String>) -> Result<()> {
+    let collection = get_collection();
+    collection.delete_many(doc! { "user_id": user_id }).await?;
+    collection
+        .insert_many(codes.into_iter().map(|code| Code {
+            code,
+            user_id: user_id.to_string(),
+        }))
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,195 @@
+use mongodb::{
+    bson::{doc, to_bson},
+    options::ReturnDocument,
+    Collection, IndexModel,
+};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::{
+    constants::EMAIL_SEND_TIMEOUT,
+    errors::{Error, Result},
+    utilities::get_time_secs,
+};
+
+static COLLECTION: OnceCell<Collection<OutboxEmail>> = OnceCell::new();
+
+// emails are queued here and delivered by a background worker, so a failing mail
+// server delays an email rather than losing it
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OutboxEmail {
+    pub id: String,
+    pub to: String,
+    pub template: String,
+    pub locale: String,
+    // rendered when the email is delivered
+    pub data: serde_json::Value,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    // while sending, this is when the claim expires and another worker may retry it
+    pub next_attempt_at: u64,
+    pub last_error: Option<String>,
+    // a pending email with the same key is replaced instead of queueing another
+    pub dedupe_key: Option<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub sent_at: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Sending,
+    Sent,
+    Failed,
+}
+
+pub fn get_collection() -> Collection<OutboxEmail> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<OutboxEmail>("email_outbox");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
+
+pub async fn create_indexes() -> Result<()> {
+    let collection = get_collection();
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "status": 1, "next_attempt_at": 1 })
+                .build(),
+        )
+        .await?;
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "dedupe_key": 1, "status": 1 })
+                .build(),
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn enqueue(
+    to: String,
+    template: &str,
+    locale: String,
+    data: serde_json::Value,
+    dedupe_key: Option<String>,
+) -> Result<()> {
+    let now = get_time_secs();
+    if let Some(key) = &dedupe_key {
+        let bson_data = to_bson(&data).map_err(|_| Error::DatabaseError)?;
+        let result = get_collection()
+            .update_one(
+                doc! {
+                    "dedupe_key": key,
+                    "status": "pending"
+                },
+                doc! {
+                    "$set": {
+                        "to": &to,
+                        "locale": &locale,
+                        "data": bson_data,
+                        "updated_at": now as i64
+                    }
+                },
+            )
+            .await?;
+        if result.matched_count > 0 {
+            return Ok(());
+        }
+    }
+    get_collection()
+        .insert_one(OutboxEmail {
+            id: Ulid::new().to_string(),
+            to,
+            template: template.to_string(),
+            locale,
+            data,
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            dedupe_key,
+            created_at: now,
+            updated_at: now,
+            sent_at: None,
+        })
+        .await?;
+    Ok(())
+}
+
+// takes the next due email, including ones whose sender stopped before finishing
+pub async fn claim() -> Result<Option<OutboxEmail>> {
+    let now = get_time_secs();
+    let email = get_collection()
+        .find_one_and_update(
+            doc! {
+                "status": { "$in": ["pending", "sending"] },
+                "next_attempt_at": { "$lte": now as i64 }
+            },
+            doc! {
+                "$set": {
+                    "status": "sending",
+                    "next_attempt_at": (now + EMAIL_SEND_TIMEOUT) as i64,
+                    "updated_at": now as i64
+                },
+                "$inc": { "attempts": 1 }
+            },
+        )
+        .sort(doc! { "next_attempt_at": 1 })
+        .return_document(ReturnDocument::After)
+        .await?;
+    Ok(email)
+}
+
+pub async fn mark_sent(id: &str) -> Result<()> {
+    let now = get_time_secs();
+    get_collection()
+        .update_one(
+            doc! {
+                "id": id
+            },
+            doc! {
+                "$set": {
+                    "status": "sent",
+                    "last_error": null,
+                    "updated_at": now as i64,
+                    "sent_at": now as i64
+                }
+            },
+        )
+        .await?;
+    Ok(())
+}
+
+// retries at `retry_at`, or gives up on the email if it's none
+pub async fn mark_failed(id: &str, error: String, retry_at: Option<u64>) -> Result<()> {
+    let now = get_time_secs();
+    let update = match retry_at {
+        Some(retry_at) => doc! {
+            "status": "pending",
+            "next_attempt_at": retry_at as i64,
+            "last_error": error,
+            "updated_at": now as i64
+        },
+        None => doc! {
+            "status": "failed",
+            "last_error": error,
+            "updated_at": now as i64
+        },
+    };
+    get_collection()
+        .update_one(doc! { "id": id }, doc! { "$set": update })
+        .await?;
+    Ok(())
+}
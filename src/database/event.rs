@@ -0,0 +1,113 @@
+use log::error;
+use mongodb::{bson::doc, options::IndexOptions, ClientSession, Collection, IndexModel};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::{errors::Result, utilities::get_time_secs};
+
+use super::{start_transaction, webhook_delivery};
+
+static COLLECTION: OnceCell<Collection<Event>> = OnceCell::new();
+
+// changes to accounts that other services need to act on; written in the same
+// transaction as their webhook deliveries, and as the change itself where it spans
+// several documents
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Event {
+    // ULID, so consumers can resume after the last event they processed
+    pub id: String,
+    pub event: EventKind,
+    pub user_id: String,
+    // details that depend on the kind of event, passed to webhooks as is
+    #[serde(default)]
+    pub data: Option<serde_json::Value>,
+    pub created_at: u64,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum EventKind {
+    #[serde(rename = "user.created")]
+    UserCreated,
+    // data: `fields`, the public profile fields that changed, named as in GET /user/{id}
+    #[serde(rename = "user.updated")]
+    UserUpdated,
+    #[serde(rename = "user.deleted")]
+    UserDeleted,
+    // data: `sessionIds`
+    #[serde(rename = "session.revoked")]
+    SessionRevoked,
+    // data: `enabled`
+    #[serde(rename = "mfa.changed")]
+    MfaChanged,
+}
+
+pub fn get_collection() -> Collection<Event> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<Event>("events");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
+
+pub async fn create_indexes() -> Result<()> {
+    get_collection()
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "id": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn emit(
+    session: &mut ClientSession,
+    event: EventKind,
+    user_id: &str,
+    data: Option<serde_json::Value>,
+) -> Result<()> {
+    let event = Event {
+        id: Ulid::new().to_string(),
+        event,
+        user_id: user_id.to_string(),
+        data,
+        created_at: get_time_secs(),
+    };
+    get_collection()
+        .insert_one(&event)
+        .session(&mut *session)
+        .await?;
+    webhook_delivery::enqueue(session, &event).await
+}
+
+// for changes made outside a transaction; emitted once the change has been made,
+// so a failure is logged rather than failing a request whose change can't be undone
+pub async fn publish(event: EventKind, user_id: &str, data: Option<serde_json::Value>) {
+    if let Err(e) = try_publish(event, user_id, data).await {
+        error!(
+            "Failed to publish {:?} event for user {}: {:?}",
+            event, user_id, e
+        );
+    }
+}
+
+async fn try_publish(
+    event: EventKind,
+    user_id: &str,
+    data: Option<serde_json::Value>,
+) -> Result<()> {
+    let mut session = start_transaction().await?;
+    if let Err(e) = emit(&mut session, event, user_id, data).await {
+        session.abort_transaction().await.ok();
+        return Err(e);
+    }
+    session.commit_transaction().await?;
+    Ok(())
+}
@@ -0,0 +1,234 @@
+use std::time::Duration;
+
+use futures_util::{Stream, TryStreamExt};
+use mongodb::{
+    bson::{doc, spec::BinarySubtype, Binary, DateTime},
+    options::IndexOptions,
+    Collection, IndexModel,
+};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::{
+    constants::{EXPORT_CHUNK_SIZE, EXPORT_LIFETIME},
+    errors::Result,
+    utilities::get_time_secs,
+};
+
+static COLLECTION: OnceCell<Collection<DataExport>> = OnceCell::new();
+static CHUNK_COLLECTION: OnceCell<Collection<ExportChunk>> = OnceCell::new();
+
+// an archive of everything stored about a user, see export::prepare
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DataExport {
+    pub id: String,
+    pub user_id: String,
+    pub status: ExportStatus,
+    pub created_at: u64,
+    // removed by the TTL index once passed
+    pub expires_at: DateTime,
+}
+
+// part of a ready export's JSON archive, which can outgrow a single document
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExportChunk {
+    pub export_id: String,
+    pub user_id: String,
+    pub index: u32,
+    pub data: Binary,
+    // removed by the TTL index along with the export
+    pub expires_at: DateTime,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportStatus {
+    Pending,
+    Ready,
+    Failed,
+}
+
+pub fn get_collection() -> Collection<DataExport> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<DataExport>("exports");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
+
+pub fn get_chunk_collection() -> Collection<ExportChunk> {
+    let collection = CHUNK_COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<ExportChunk>("export_chunks");
+        CHUNK_COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
+
+pub async fn create_indexes() -> Result<()> {
+    let collection = get_collection();
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "id": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+    collection
+        .create_index(IndexModel::builder().keys(doc! { "user_id": 1 }).build())
+        .await?;
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "expires_at": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Duration::from_secs(0))
+                        .build(),
+                )
+                .build(),
+        )
+        .await?;
+    let chunks = get_chunk_collection();
+    chunks
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "export_id": 1, "index": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+    chunks
+        .create_index(IndexModel::builder().keys(doc! { "user_id": 1 }).build())
+        .await?;
+    chunks
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "expires_at": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Duration::from_secs(0))
+                        .build(),
+                )
+                .build(),
+        )
+        .await?;
+    Ok(())
+}
+
+fn get_expiry() -> DateTime {
+    DateTime::from_millis(((get_time_secs() + EXPORT_LIFETIME) * 1000) as i64)
+}
+
+pub async fn create(user_id: &str) -> Result<DataExport> {
+    let export = DataExport {
+        id: Ulid::new().to_string(),
+        user_id: user_id.to_string(),
+        status: ExportStatus::Pending,
+        created_at: get_time_secs(),
+        expires_at: get_expiry(),
+    };
+    get_collection().insert_one(&export).await?;
+    Ok(export)
+}
+
+// ignores exports the TTL index hasn't removed yet
+pub async fn get(id: &str, user_id: &str) -> Result<Option<DataExport>> {
+    let export = get_collection()
+        .find_one(doc! {
+            "id": id,
+            "user_id": user_id,
+            "expires_at": { "$gt": DateTime::now() }
+        })
+        .await?;
+    Ok(export)
+}
+
+pub async fn get_pending(user_id: &str) -> Result<Option<DataExport>> {
+    let export = get_collection()
+        .find_one(doc! {
+            "user_id": user_id,
+            "status": "pending",
+            "expires_at": { "$gt": DateTime::now() }
+        })
+        .await?;
+    Ok(export)
+}
+
+// stores the archive and returns the new expiry, as the download is available for
+// EXPORT_LIFETIME from now
+pub async fn mark_ready(export: &DataExport, data: &[u8]) -> Result<DateTime> {
+    let expires_at = get_expiry();
+    let chunks = data
+        .chunks(EXPORT_CHUNK_SIZE)
+        .enumerate()
+        .map(|(index, chunk)| ExportChunk {
+            export_id: export.id.clone(),
+            user_id: export.user_id.clone(),
+            index: index as u32,
+            data: Binary {
+                subtype: BinarySubtype::Generic,
+                bytes: chunk.to_vec(),
+            },
+            expires_at,
+        })
+        .collect::<Vec<_>>();
+    // insert_many splits the batch to stay under the message size limit
+    if !chunks.is_empty() {
+        get_chunk_collection().insert_many(chunks).await?;
+    }
+    get_collection()
+        .update_one(
+            doc! {
+                "id": &export.id
+            },
+            doc! {
+                "$set": {
+                    "status": "ready",
+                    "expires_at": expires_at
+                }
+            },
+        )
+        .await?;
+    Ok(expires_at)
+}
+
+pub async fn mark_failed(id: &str) -> Result<()> {
+    get_collection()
+        .update_one(
+            doc! {
+                "id": id
+            },
+            doc! {
+                "$set": {
+                    "status": "failed"
+                }
+            },
+        )
+        .await?;
+    Ok(())
+}
+
+// the archive's bytes in order, for streaming to the user
+pub async fn read_archive(
+    id: &str,
+) -> Result<impl Stream<Item = std::result::Result<Vec<u8>, mongodb::error::Error>>> {
+    let chunks = get_chunk_collection()
+        .find(doc! {
+            "export_id": id
+        })
+        .sort(doc! { "index": 1 })
+        .await?;
+    Ok(chunks.map_ok(|chunk| chunk.data.bytes))
+}
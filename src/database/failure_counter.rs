@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use mongodb::{
+    bson::{doc, to_bson, Bson, DateTime},
+    options::{IndexOptions, ReturnDocument},
+    Collection, IndexModel,
+};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::{Error, Result},
+    utilities::get_time_secs,
+};
+
+static COLLECTION: OnceCell<Collection<FailureCounter>> = OnceCell::new();
+
+// counts failed attempts against a key within a window starting at the first failure
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FailureCounter {
+    pub scope: FailureScope,
+    pub key: String,
+    pub count: u64,
+    #[serde(default)]
+    pub last_failed_at: u64,
+    // attempts are refused until then, see risk::check_account_throttle
+    #[serde(default)]
+    pub locked_until: Option<u64>,
+    // removed by the TTL index once passed
+    pub expires_at: DateTime,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureScope {
+    LoginEmail,
+    LoginIp,
+    // failed passwords and MFA codes for a user ID
+    Account,
+    // failed MFA codes for a login's continue token
+    MfaToken,
+}
+
+pub fn get_collection() -> Collection<FailureCounter> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<FailureCounter>("failure_counters");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
+
+pub async fn create_indexes() -> Result<()> {
+    let collection = get_collection();
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "scope": 1, "key": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "expires_at": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Duration::from_secs(0))
+                        .build(),
+                )
+                .build(),
+        )
+        .await?;
+    Ok(())
+}
+
+fn scope_to_bson(scope: FailureScope) -> Result<Bson> {
+    to_bson(&scope).map_err(|_| Error::DatabaseError)
+}
+
+fn get_expiry(secs: u64) -> DateTime {
+    DateTime::from_millis((secs * 1000) as i64)
+}
+
+// returns the counter with this failure included
+pub async fn increment(scope: FailureScope, key: &str, window: u64) -> Result<FailureCounter> {
+    let now = get_time_secs();
+    let scope = scope_to_bson(scope)?;
+    let collection = get_collection();
+    // the TTL index may not have removed an expired counter yet
+    collection
+        .delete_one(doc! {
+            "scope": scope.clone(),
+            "key": key,
+            "expires_at": { "$lte": get_expiry(now) }
+        })
+        .await?;
+    let counter = collection
+        .find_one_and_update(
+            doc! {
+                "scope": scope.clone(),
+                "key": key
+            },
+            doc! {
+                "$inc": { "count": 1 },
+                "$set": { "last_failed_at": now as i64 },
+                "$setOnInsert": { "expires_at": get_expiry(now + window) }
+            },
+        )
+        .upsert(true)
+        .return_document(ReturnDocument::After)
+        .await?;
+    counter.ok_or(Error::DatabaseError)
+}
+
+pub async fn get(scope: FailureScope, key: &str) -> Result<Option<FailureCounter>> {
+    let counter = get_collection()
+        .find_one(doc! {
+            "scope": scope_to_bson(scope)?,
+            "key": key,
+            "expires_at": { "$gt": get_expiry(get_time_secs()) }
+        })
+        .await?;
+    Ok(counter)
+}
+
+pub async fn get_count(scope: FailureScope, key: &str) -> Result<u64> {
+    Ok(get(scope, key)
+        .await?
+        .map(|counter| counter.count)
+        .unwrap_or(0))
+}
+
+// returns false if the key was already locked, so only one caller acts on a lock
+pub async fn lock(scope: FailureScope, key: &str, until: u64) -> Result<bool> {
+    let now = get_time_secs();
+    let result = get_collection()
+        .update_one(
+            doc! {
+                "scope": scope_to_bson(scope)?,
+                "key": key,
+                "$or": [
+                    { "locked_until": null },
+                    { "locked_until": { "$lte": now as i64 } }
+                ]
+            },
+            doc! {
+                "$set": { "locked_until": until as i64 },
+                // kept at least as long as the lock
+                "$max": { "expires_at": get_expiry(until) }
+            },
+        )
+        .await?;
+    Ok(result.modified_count > 0)
+}
+
+pub async fn clear(scope: FailureScope, key: &str) -> Result<()> {
+    get_collection()
+        .delete_one(doc! {
+            "scope": scope_to_bson(scope)?,
+            "key": key
+        })
+        .await?;
+    Ok(())
+}
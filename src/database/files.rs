@@ -1,4 +1,4 @@
-use mongodb::{bson::doc, Collection};
+use mongodb::{bson::doc, ClientSession, Collection};
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 
@@ -62,11 +62,13 @@ impl File {
             .ok_or(Error::DatabaseError)
     }
 
+    // fails if the file was attached elsewhere in the meantime
     pub async fn attach(&self) -> Result<()> {
-        get_collection()
+        let result = get_collection()
             .update_one(
                 doc! {
                     "id": &self.id,
+                    "attached": false,
                     "deleted": false,
                     "flagged": false,
                 },
@@ -77,6 +79,9 @@ impl File {
                 },
             )
             .await?;
+        if result.matched_count == 0 {
+            return Err(Error::InvalidAvatar);
+        }
         Ok(())
     }
 
@@ -97,4 +102,24 @@ impl File {
             .await?;
         Ok(())
     }
+
+    // detaches the file as part of a transaction, without reading it first
+    pub async fn detach_by_id(id: &str, session: &mut ClientSession) -> Result<()> {
+        get_collection()
+            .update_one(
+                doc! {
+                    "id": id,
+                    "deleted": false,
+                    "flagged": false,
+                },
+                doc! {
+                    "$set": {
+                        "attached": false,
+                    },
+                },
+            )
+            .session(session)
+            .await?;
+        Ok(())
+    }
 }
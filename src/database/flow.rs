@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use mongodb::{
+    bson::{doc, DateTime, Document},
+    options::IndexOptions,
+    Collection, IndexModel,
+};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::Result;
+
+static COLLECTION: OnceCell<Collection<FlowEntry>> = OnceCell::new();
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FlowEntry {
+    pub flow: String,
+    pub key: String,
+    pub data: Document,
+    // removed by the TTL index once passed
+    pub expires_at: DateTime,
+}
+
+pub fn get_collection() -> Collection<FlowEntry> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<FlowEntry>("flows");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
+
+pub async fn create_indexes() -> Result<()> {
+    let collection = get_collection();
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "flow": 1, "key": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "expires_at": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Duration::from_secs(0))
+                        .build(),
+                )
+                .build(),
+        )
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use mongodb::{bson::doc, options::IndexOptions, Collection, IndexModel};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::Result;
+
+static COLLECTION: OnceCell<Collection<MachineClient>> = OnceCell::new();
+
+// a backend service that calls the API as itself rather than as a user, see machine.rs
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MachineClient {
+    pub id: String,
+    pub name: String,
+    // SHA-256 hash of the client secret; none for clients that sign assertions instead
+    pub secret: Option<String>,
+    // PEM-encoded EC, Ed25519 or RSA key that verifies the client's JWT assertions
+    pub public_key: Option<String>,
+    pub scopes: Vec<MachineScope>,
+    pub created_by: String,
+    pub created_at: u64,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum MachineScope {
+    // look up users' public profiles in bulk
+    #[serde(rename = "users:read")]
+    UsersRead,
+    // check user access tokens and find out who they belong to
+    #[serde(rename = "tokens:verify")]
+    TokensVerify,
+}
+
+impl MachineScope {
+    pub const ALL: [MachineScope; 2] = [MachineScope::UsersRead, MachineScope::TokensVerify];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MachineScope::UsersRead => "users:read",
+            MachineScope::TokensVerify => "tokens:verify",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<MachineScope> {
+        MachineScope::ALL
+            .into_iter()
+            .find(|candidate| candidate.as_str() == scope)
+    }
+}
+
+pub fn get_collection() -> Collection<MachineClient> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<MachineClient>("machine_clients");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
+
+pub async fn create_indexes() -> Result<()> {
+    get_collection()
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "id": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+    Ok(())
+}
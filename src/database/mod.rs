@@ -1,19 +1,48 @@
+pub mod admin_action;
+pub mod audit_event;
+pub mod client;
 pub mod code;
+pub mod email_outbox;
+pub mod event;
+pub mod export;
+pub mod failure_counter;
 pub mod files;
+pub mod flow;
+pub mod machine_client;
 pub mod passkey;
 pub mod profile;
 pub mod session;
 pub mod settings;
 pub mod user;
+pub mod webhook;
+pub mod webhook_delivery;
 
 use log::info;
-use mongodb::{Client, Database};
+use mongodb::{Client, ClientSession, Database};
 use once_cell::sync::OnceCell;
 
-use crate::environment::{MONGODB_DATABASE, MONGODB_URI};
+use crate::{
+    environment::{MONGODB_DATABASE, MONGODB_URI},
+    errors::Result,
+};
 
 static DATABASE: OnceCell<Client> = OnceCell::new();
 
+// every collection holding data that belongs to a single user, with the field
+// holding the user's ID; an account is purged from all of them at once, so add new
+// collections here. Collections keyed some other way, such as failure counters and
+// the email outbox, are cleared in deletion::purge_in_transaction. Audit events and
+// admin actions are kept on purpose.
+pub const USER_COLLECTIONS: [(&str, &str); 7] = [
+    ("users", "id"),
+    ("profiles", "id"),
+    ("sessions", "user_id"),
+    ("passkeys", "user_id"),
+    ("codes", "user_id"),
+    ("exports", "user_id"),
+    ("export_chunks", "user_id"),
+];
+
 pub async fn connect() {
     let client = Client::with_uri_str(&*MONGODB_URI)
         .await
@@ -29,3 +58,10 @@ pub fn get_connection() -> &'static Client {
 pub fn get_database() -> Database {
     get_connection().database(&MONGODB_DATABASE)
 }
+
+// multi-document transactions need MongoDB to run as a replica set
+pub async fn start_transaction() -> Result<ClientSession> {
+    let mut session = get_connection().start_session().await?;
+    session.start_transaction().await?;
+    Ok(session)
+}
@@ -1,15 +1,68 @@
-use mongodb::{bson::doc, Collection};
+use std::collections::BTreeMap;
+
+use actix_web::HttpRequest;
+use futures_util::TryStreamExt;
+use mongodb::{
+    bson::{doc, Document},
+    ClientSession, Collection,
+};
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use ulid::Ulid;
+use woothee::parser::Parser;
+
+use crate::{
+    constants::{LONG_SESSION, SHORT_SESSION},
+    errors::Result,
+    geoip,
+    utilities::{generate_continue_token_long, get_time_secs, hash_secret},
+};
+
+use super::{
+    event::{self, EventKind},
+    start_transaction,
+};
 
 static COLLECTION: OnceCell<Collection<Session>> = OnceCell::new();
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Session {
     pub id: String,
-    pub token: String,
+    // only set on sessions created before refresh tokens, which use one long-lived JWT
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    // hashed with hash_secret; rotated on every refresh
+    pub refresh_token: Option<String>,
+    // refresh tokens that have already been used; presenting one again revokes the session
+    #[serde(default)]
+    pub previous_refresh_tokens: Vec<String>,
+    #[serde(default)]
+    pub persistent: bool,
+    // refresh tokens are rejected after this time (seconds); extended on every refresh
+    #[serde(default)]
+    pub expires_at: u64,
     pub friendly_name: String,
     pub user_id: String,
+    // sessions created before metadata was recorded default to 0 / None
+    #[serde(default)]
+    pub created_at: u64,
+    #[serde(default)]
+    pub last_used_at: u64,
+    pub ip: Option<String>,
+    pub user_agent: Option<UserAgent>,
+    pub location: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserAgent {
+    pub browser: String,
+    pub browser_version: String,
+    pub os: String,
+    pub os_version: String,
+    // pc, smartphone, mobilephone, appliance, crawler, misc or UNKNOWN
+    pub category: String,
 }
 
 pub fn get_collection() -> Collection<Session> {
@@ -24,3 +77,129 @@ pub fn get_collection() -> Collection<Session> {
         c
     }
 }
+
+pub fn parse_user_agent(user_agent: &str) -> Option<UserAgent> {
+    let result = Parser::new().parse(user_agent)?;
+    Some(UserAgent {
+        browser: result.name.to_string(),
+        browser_version: result.version.to_string(),
+        os: result.os.to_string(),
+        os_version: result.os_version.to_string(),
+        category: result.category.to_string(),
+    })
+}
+
+pub fn get_ip(req: &HttpRequest) -> Option<String> {
+    req.connection_info()
+        .realip_remote_addr()
+        .map(|ip| ip.to_string())
+}
+
+pub fn get_lifetime(persistent: bool) -> u64 {
+    let lifetime = if persistent {
+        LONG_SESSION
+    } else {
+        SHORT_SESSION
+    };
+    (lifetime / 1000) as u64
+}
+
+impl Session {
+    // returns the session along with its unhashed refresh token
+    pub fn new(
+        user_id: String,
+        friendly_name: String,
+        persistent: bool,
+        req: &HttpRequest,
+    ) -> (Self, String) {
+        let now = get_time_secs();
+        let refresh_token = generate_continue_token_long();
+        let ip = get_ip(req);
+        let user_agent = req
+            .headers()
+            .get("User-Agent")
+            .and_then(|ua| ua.to_str().ok())
+            .and_then(parse_user_agent);
+        let session = Session {
+            id: Ulid::new().to_string(),
+            token: None,
+            refresh_token: Some(hash_secret(&refresh_token)),
+            previous_refresh_tokens: Vec::new(),
+            persistent,
+            expires_at: now + get_lifetime(persistent),
+            friendly_name,
+            user_id,
+            created_at: now,
+            last_used_at: now,
+            location: ip.as_deref().and_then(geoip::lookup),
+            ip,
+            user_agent,
+        };
+        (session, refresh_token)
+    }
+}
+
+// records activity on a session; callers throttle with SESSION_ACTIVITY_INTERVAL
+pub async fn touch(id: String, ip: Option<String>) -> Result<()> {
+    let mut update = doc! {
+        "last_used_at": get_time_secs() as i64
+    };
+    if let Some(ip) = ip {
+        update.insert("location", geoip::lookup(&ip));
+        update.insert("ip", ip);
+    }
+    get_collection()
+        .update_one(doc! { "id": id }, doc! { "$set": update })
+        .await?;
+    Ok(())
+}
+
+async fn revoke_in_transaction(transaction: &mut ClientSession, filter: Document) -> Result<u64> {
+    let mut cursor = get_collection()
+        .find(filter)
+        .session(&mut *transaction)
+        .await?;
+    let revoked = cursor
+        .stream(&mut *transaction)
+        .try_collect::<Vec<Session>>()
+        .await?;
+    let mut by_user = BTreeMap::<String, Vec<String>>::new();
+    for revoked in &revoked {
+        by_user
+            .entry(revoked.user_id.clone())
+            .or_default()
+            .push(revoked.id.clone());
+    }
+    for (user_id, ids) in by_user {
+        get_collection()
+            .delete_many(doc! {
+                "id": { "$in": ids.clone() }
+            })
+            .session(&mut *transaction)
+            .await?;
+        event::emit(
+            transaction,
+            EventKind::SessionRevoked,
+            &user_id,
+            Some(json!({ "sessionIds": ids })),
+        )
+        .await?;
+    }
+    Ok(revoked.len() as u64)
+}
+
+// signs out the matching sessions, emitting session.revoked for each user affected;
+// returns how many were revoked
+pub async fn revoke(filter: Document) -> Result<u64> {
+    let mut transaction = start_transaction().await?;
+    match revoke_in_transaction(&mut transaction, filter).await {
+        Ok(count) => {
+            transaction.commit_transaction().await?;
+            Ok(count)
+        }
+        Err(e) => {
+            transaction.abort_transaction().await.ok();
+            Err(e)
+        }
+    }
+}
@@ -4,13 +4,49 @@ use mongodb::{bson::doc, Collection};
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 
-use crate::opaque::create_server_setup;
+use crate::{encryption::create_encryption_key, opaque::create_server_setup};
 
 static COLLECTION: OnceCell<Collection<Settings>> = OnceCell::new();
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Settings {
     pub opaque_server_setup: Vec<u8>,
+    #[serde(default)]
+    pub signing_keys: Vec<SigningKey>,
+    // unused when ENCRYPTION_KEYS is set
+    #[serde(default)]
+    pub encryption_keys: Vec<EncryptionKey>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyAlgorithm {
+    #[default]
+    ES256,
+    EdDSA,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigningKey {
+    pub id: String,
+    #[serde(default)]
+    pub algorithm: KeyAlgorithm,
+    // base64 PKCS#8 document, encrypted with encryption::seal
+    pub private_key: String,
+    // uncompressed curve point for ES256, raw key for EdDSA
+    pub public_key: Vec<u8>,
+    pub created_at: u64,
+    // published in the JWKS before this so that verifiers have it by the time it signs
+    #[serde(default)]
+    pub activates_at: u64,
+    // retired keys no longer sign but still verify until the overlap window ends
+    #[serde(default)]
+    pub retired_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionKey {
+    pub version: u32,
+    pub key: Vec<u8>,
 }
 
 pub fn get_collection() -> Collection<Settings> {
@@ -33,6 +69,10 @@ pub async fn get_settings() -> Settings {
     } else {
         let settings = Settings {
             opaque_server_setup: create_server_setup().serialize().as_slice().to_vec(),
+            // sealing needs the encryption keys above, so the first signing key is
+            // created by signing::load_signing_keys once these settings exist
+            signing_keys: Vec::new(),
+            encryption_keys: vec![create_encryption_key(1)],
         };
         collection.insert_one(&settings).await.unwrap();
         settings
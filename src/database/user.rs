@@ -2,6 +2,11 @@ use mongodb::Collection;
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    errors::{Error, Result},
+    utilities::get_time_secs,
+};
+
 static COLLECTION: OnceCell<Collection<User>> = OnceCell::new();
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -11,11 +16,57 @@ pub struct User {
     pub password_data: Vec<u8>,
     pub username: String,
     pub mfa_enabled: bool,
+    // encrypted with encryption::seal
     pub mfa_secret: Option<String>,
     pub platform_administrator: bool,
+    #[serde(default)]
+    pub status: AccountStatus,
+    // BCP 47 language tag for emails; resolved against the available templates when sending
+    #[serde(default)]
+    pub locale: Option<String>,
+    // set while the account is waiting to be purged, see deletion::schedule
+    #[serde(default)]
+    pub deletion: Option<ScheduledDeletion>,
     // Recovery email, client-encrypted keys?
 }
 
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    // set by platform administrators; lifts itself once `until` (seconds) has passed
+    Suspended {
+        reason: String,
+        until: Option<u64>,
+    },
+    // the user must verify their identity before signing in again
+    Locked,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ScheduledDeletion {
+    pub purge_at: u64,
+    // SHA-256 of the token in the cancel link
+    pub cancel_token: String,
+}
+
+impl User {
+    pub fn ensure_active(&self) -> Result<()> {
+        match &self.status {
+            AccountStatus::Active => Ok(()),
+            AccountStatus::Suspended {
+                until: Some(until), ..
+            } if *until <= get_time_secs() => Ok(()),
+            AccountStatus::Suspended { reason, until } => Err(Error::AccountSuspended {
+                reason: reason.clone(),
+                until: *until,
+            }),
+            AccountStatus::Locked => Err(Error::AccountLocked),
+        }
+    }
+}
+
 pub fn get_collection() -> Collection<User> {
     let collection = COLLECTION.get();
     if let Some(c) = collection {
@@ -0,0 +1,51 @@
+use mongodb::{bson::doc, options::IndexOptions, Collection, IndexModel};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::Result;
+
+use super::event::EventKind;
+
+static COLLECTION: OnceCell<Collection<Webhook>> = OnceCell::new();
+
+// an endpoint in another service that is sent the events it subscribes to
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    pub description: String,
+    pub events: Vec<EventKind>,
+    // encrypted with encryption::seal; payloads are signed with it, see webhooks::sign
+    pub secret: String,
+    pub created_by: String,
+    pub created_at: u64,
+}
+
+pub fn get_collection() -> Collection<Webhook> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<Webhook>("webhooks");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
+
+pub async fn create_indexes() -> Result<()> {
+    let collection = get_collection();
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "id": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+    collection
+        .create_index(IndexModel::builder().keys(doc! { "events": 1 }).build())
+        .await?;
+    Ok(())
+}
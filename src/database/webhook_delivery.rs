@@ -0,0 +1,230 @@
+use futures_util::TryStreamExt;
+use mongodb::{
+    bson::{doc, to_bson, Document},
+    options::ReturnDocument,
+    ClientSession, Collection, IndexModel,
+};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::{
+    constants::WEBHOOK_SEND_TIMEOUT,
+    errors::{Error, Result},
+    utilities::get_time_secs,
+};
+
+use super::{
+    email_outbox::DeliveryStatus,
+    event::{Event, EventKind},
+    webhook,
+};
+
+static COLLECTION: OnceCell<Collection<WebhookDelivery>> = OnceCell::new();
+
+// an event queued for, or sent to, one webhook; kept as the endpoint's delivery log
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WebhookDelivery {
+    pub id: String,
+    pub webhook_id: String,
+    pub event_id: String,
+    pub event: EventKind,
+    // the JSON body, fixed when queued so every attempt sends the same bytes
+    pub payload: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    // while sending, this is when the claim expires and another worker may retry it
+    pub next_attempt_at: u64,
+    // of the last attempt; none if the endpoint couldn't be reached
+    pub response_status: Option<u16>,
+    pub last_error: Option<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub delivered_at: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Payload<'a> {
+    id: &'a str,
+    event: EventKind,
+    user_id: &'a str,
+    data: &'a Option<serde_json::Value>,
+    created_at: u64,
+}
+
+pub fn get_collection() -> Collection<WebhookDelivery> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<WebhookDelivery>("webhook_deliveries");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
+
+pub async fn create_indexes() -> Result<()> {
+    let collection = get_collection();
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "status": 1, "next_attempt_at": 1 })
+                .build(),
+        )
+        .await?;
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "webhook_id": 1, "id": -1 })
+                .build(),
+        )
+        .await?;
+    Ok(())
+}
+
+// queues the event for every webhook subscribed to it, in the event's transaction
+pub async fn enqueue(session: &mut ClientSession, event: &Event) -> Result<()> {
+    let mut cursor = webhook::get_collection()
+        .find(doc! {
+            "events": to_bson(&event.event).map_err(|_| Error::DatabaseError)?
+        })
+        .session(&mut *session)
+        .await?;
+    let webhooks = cursor.stream(&mut *session).try_collect::<Vec<_>>().await?;
+    if webhooks.is_empty() {
+        return Ok(());
+    }
+    let payload = serde_json::to_string(&Payload {
+        id: &event.id,
+        event: event.event,
+        user_id: &event.user_id,
+        data: &event.data,
+        created_at: event.created_at,
+    })
+    .map_err(|_| Error::DatabaseError)?;
+    let now = get_time_secs();
+    let deliveries = webhooks.into_iter().map(|webhook| WebhookDelivery {
+        id: Ulid::new().to_string(),
+        webhook_id: webhook.id,
+        event_id: event.id.clone(),
+        event: event.event,
+        payload: payload.clone(),
+        status: DeliveryStatus::Pending,
+        attempts: 0,
+        next_attempt_at: now,
+        response_status: None,
+        last_error: None,
+        created_at: now,
+        updated_at: now,
+        delivered_at: None,
+    });
+    get_collection()
+        .insert_many(deliveries)
+        .session(&mut *session)
+        .await?;
+    Ok(())
+}
+
+// takes the next due delivery, including ones whose sender stopped before finishing
+pub async fn claim() -> Result<Option<WebhookDelivery>> {
+    let now = get_time_secs();
+    let delivery = get_collection()
+        .find_one_and_update(
+            doc! {
+                "status": { "$in": ["pending", "sending"] },
+                "next_attempt_at": { "$lte": now as i64 }
+            },
+            doc! {
+                "$set": {
+                    "status": "sending",
+                    "next_attempt_at": (now + WEBHOOK_SEND_TIMEOUT) as i64,
+                    "updated_at": now as i64
+                },
+                "$inc": { "attempts": 1 }
+            },
+        )
+        .sort(doc! { "next_attempt_at": 1 })
+        .return_document(ReturnDocument::After)
+        .await?;
+    Ok(delivery)
+}
+
+pub async fn mark_delivered(id: &str, response_status: u16) -> Result<()> {
+    let now = get_time_secs();
+    get_collection()
+        .update_one(
+            doc! {
+                "id": id
+            },
+            doc! {
+                "$set": {
+                    "status": "sent",
+                    "response_status": response_status as i32,
+                    "last_error": null,
+                    "updated_at": now as i64,
+                    "delivered_at": now as i64
+                }
+            },
+        )
+        .await?;
+    Ok(())
+}
+
+// retries at `retry_at`, or gives up on the delivery if it's none
+pub async fn mark_failed(
+    id: &str,
+    response_status: Option<u16>,
+    error: String,
+    retry_at: Option<u64>,
+) -> Result<()> {
+    let now = get_time_secs();
+    let mut update = match retry_at {
+        Some(retry_at) => doc! {
+            "status": "pending",
+            "next_attempt_at": retry_at as i64
+        },
+        None => doc! {
+            "status": "failed"
+        },
+    };
+    update.insert(
+        "response_status",
+        response_status.map(|status| status as i32),
+    );
+    update.insert("last_error", error);
+    update.insert("updated_at", now as i64);
+    get_collection()
+        .update_one(doc! { "id": id }, doc! { "$set": update })
+        .await?;
+    Ok(())
+}
+
+// newest first; pass the last ID as `before` for the next page
+pub async fn query(
+    webhook_id: &str,
+    before: Option<String>,
+    limit: i64,
+) -> Result<(Vec<WebhookDelivery>, Option<String>)> {
+    let mut filter = Document::new();
+    filter.insert("webhook_id", webhook_id);
+    if let Some(before) = before {
+        filter.insert("id", doc! { "$lt": before });
+    }
+    let mut deliveries = get_collection()
+        .find(filter)
+        .sort(doc! { "id": -1 })
+        .limit(limit + 1)
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    let next_cursor = if deliveries.len() as i64 > limit {
+        deliveries.truncate(limit as usize);
+        deliveries.last().map(|delivery| delivery.id.clone())
+    } else {
+        None
+    };
+    Ok((deliveries, next_cursor))
+}
@@ -0,0 +1,218 @@
+use actix_web::HttpRequest;
+use async_std::task;
+use futures_util::StreamExt;
+use log::{error, info};
+use mongodb::{
+    bson::{doc, DateTime, Document},
+    ClientSession,
+};
+use serde_json::json;
+
+use crate::{
+    database::{
+        audit_event::{self, AuditEventKind},
+        email_outbox,
+        event::{self, EventKind},
+        failure_counter,
+        files::File,
+        get_database, profile, session, start_transaction,
+        user::{self, User},
+        USER_COLLECTIONS,
+    },
+    environment::{DELETION_GRACE_PERIOD, PUBLIC_ROOT},
+    errors::Result,
+    notifications::notice_context,
+    risk::normalize_email,
+    templates::resolve_locale,
+    utilities::{generate_continue_token_long, get_time_secs, hash_secret, send_template_email},
+};
+
+// Deleting an account only schedules it to be purged once DELETION_GRACE_PERIOD has
+// passed. Until then the user can change their mind by following the link in the
+// confirmation email or by signing in again; purge_due does the final removal, in a
+// single transaction across every collection in USER_COLLECTIONS, the user's failure
+// counters and queued emails and the CDN, along with a user.deleted event for other
+// services.
+
+// signs the account out everywhere and emails the user a link to cancel
+pub async fn schedule(req: &HttpRequest, user: &User) -> Result<u64> {
+    // prepared first, so the account isn't changed if the lockdown link can't be made
+    let mut data = notice_context(req, user).await?;
+    let purge_at = get_time_secs() + *DELETION_GRACE_PERIOD;
+    let cancel_token = generate_continue_token_long();
+    user::get_collection()
+        .update_one(
+            doc! {
+                "id": &user.id
+            },
+            doc! {
+                "$set": {
+                    "deletion": {
+                        "purge_at": purge_at as i64,
+                        "cancel_token": hash_secret(&cancel_token)
+                    }
+                }
+            },
+        )
+        .await?;
+    session::revoke(doc! {
+        "user_id": &user.id
+    })
+    .await?;
+    let purge_time = DateTime::from_millis((purge_at * 1000) as i64)
+        .try_to_rfc3339_string()
+        .unwrap_or_default();
+    data["purge_at"] = json!(purge_time);
+    data["cancel_url"] = json!(format!("{}/restore?token={}", &*PUBLIC_ROOT, cancel_token));
+    task::spawn(send_template_email(
+        user.email.clone(),
+        "deletion_scheduled",
+        resolve_locale(user.locale.as_deref(), Some(req)),
+        data,
+    ));
+    Ok(purge_at)
+}
+
+// returns whether a deletion was cancelled
+pub async fn cancel(req: &HttpRequest, user: &User) -> Result<bool> {
+    let result = user::get_collection()
+        .update_one(
+            doc! {
+                "id": &user.id,
+                "deletion": { "$ne": null }
+            },
+            doc! {
+                "$unset": {
+                    "deletion": ""
+                }
+            },
+        )
+        .await?;
+    if result.modified_count == 0 {
+        return Ok(false);
+    }
+    audit_event::record(
+        req,
+        AuditEventKind::DeletionCancelled,
+        Some(&user.id),
+        &user.id,
+        None,
+        None,
+    )
+    .await;
+    Ok(true)
+}
+
+// finds the account a cancel link was sent to, if it is still scheduled for deletion
+pub async fn find_by_cancel_token(token: &str) -> Result<Option<User>> {
+    let user = user::get_collection()
+        .find_one(doc! {
+            "deletion.cancel_token": hash_secret(token),
+            "deletion.purge_at": { "$gt": get_time_secs() as i64 }
+        })
+        .await?;
+    Ok(user)
+}
+
+// returns false if the account is no longer due to be purged
+async fn purge_in_transaction(session: &mut ClientSession, user: &User) -> Result<bool> {
+    // the user may have signed in again since, or another replica purged it first
+    let result = user::get_collection()
+        .delete_one(doc! {
+            "id": &user.id,
+            "deletion.purge_at": { "$lte": get_time_secs() as i64 }
+        })
+        .session(&mut *session)
+        .await?;
+    if result.deleted_count == 0 {
+        return Ok(false);
+    }
+    let profile = profile::get_collection()
+        .find_one(doc! {
+            "id": &user.id
+        })
+        .session(&mut *session)
+        .await?;
+    if let Some(avatar) = profile
+        .and_then(|profile| profile.avatar)
+        .filter(|avatar| avatar != "default")
+    {
+        File::detach_by_id(&avatar, session).await?;
+    }
+    let database = get_database();
+    for (collection, field) in USER_COLLECTIONS {
+        let mut filter = Document::new();
+        filter.insert(field, user.id.as_str());
+        database
+            .collection::<Document>(collection)
+            .delete_many(filter)
+            .session(&mut *session)
+            .await?;
+    }
+    // keyed by the user's ID or address rather than a field of their own
+    failure_counter::get_collection()
+        .delete_many(doc! {
+            "$or": [
+                { "scope": "account", "key": &user.id },
+                { "scope": "login_email", "key": normalize_email(&user.email) },
+            ]
+        })
+        .session(&mut *session)
+        .await?;
+    email_outbox::get_collection()
+        .delete_many(doc! {
+            "to": &user.email
+        })
+        .session(&mut *session)
+        .await?;
+    event::emit(session, EventKind::UserDeleted, &user.id, None).await?;
+    Ok(true)
+}
+
+async fn purge(user: &User) -> Result<()> {
+    let mut session = start_transaction().await?;
+    match purge_in_transaction(&mut session, user).await {
+        Ok(true) => session.commit_transaction().await?,
+        Ok(false) => {
+            session.abort_transaction().await?;
+            return Ok(());
+        }
+        Err(e) => {
+            session.abort_transaction().await.ok();
+            return Err(e);
+        }
+    }
+    // kept after the account is gone, so the deletion can still be traced
+    audit_event::record_system(AuditEventKind::AccountDeleted, &user.id, None).await;
+    Ok(())
+}
+
+// removes every account whose grace period has passed
+pub async fn purge_due() {
+    let now = get_time_secs();
+    let users = user::get_collection()
+        .find(doc! {
+            "deletion.purge_at": { "$lte": now as i64 }
+        })
+        .await;
+    let users = match users {
+        Ok(users) => users.collect::<Vec<_>>().await,
+        Err(e) => {
+            error!("Failed to find accounts to delete: {}", e);
+            return;
+        }
+    };
+    for user in users {
+        let user: User = match user {
+            Ok(user) => user,
+            Err(e) => {
+                error!("Failed to read account to delete: {}", e);
+                continue;
+            }
+        };
+        match purge(&user).await {
+            Ok(()) => info!("Deleted account {}", user.id),
+            Err(e) => error!("Failed to delete account {}: {:?}", user.id, e),
+        }
+    }
+}
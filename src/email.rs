@@ -0,0 +1,271 @@
+use std::path::PathBuf;
+
+use async_std::fs;
+use async_trait::async_trait;
+use lettre::{
+    message::MultiPart, transport::smtp::authentication::Credentials, AsyncFileTransport,
+    AsyncSendmailTransport, AsyncSmtpTransport, AsyncStd1Executor, AsyncTransport, Message,
+};
+use log::{error, info, warn};
+use once_cell::sync::OnceCell;
+use ulid::Ulid;
+
+use crate::{
+    constants::{EMAIL_MAX_ATTEMPTS, EMAIL_RETRY_BASE, EMAIL_RETRY_MAX},
+    database::email_outbox::{self, OutboxEmail},
+    environment::{
+        EMAIL_FROM, EMAIL_SPOOL_DIR, EMAIL_TRANSPORT, SENDMAIL_COMMAND, SMTP_PASSWORD, SMTP_SERVER,
+        SMTP_USERNAME,
+    },
+    templates,
+    utilities::get_time_secs,
+};
+
+// Outgoing email is handed to an EmailTransport chosen with EMAIL_TRANSPORT. Besides
+// SMTP, messages can go to the local sendmail binary, a maildir, or a directory of
+// .eml files that tests and development environments can read codes from.
+// Emails are queued in the outbox and delivered by deliver_queued, which retries
+// transient failures with exponential backoff.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmailBackend {
+    Smtp,
+    Sendmail,
+    Maildir,
+    File,
+    // emails can't be sent; registration hands the verification code back instead
+    Disabled,
+}
+
+#[derive(Debug)]
+pub struct DeliveryError {
+    pub message: String,
+    // retrying won't help, e.g. the recipient was rejected
+    pub permanent: bool,
+}
+
+impl DeliveryError {
+    fn transient(message: String) -> Self {
+        DeliveryError {
+            message,
+            permanent: false,
+        }
+    }
+
+    fn permanent(message: String) -> Self {
+        DeliveryError {
+            message,
+            permanent: true,
+        }
+    }
+}
+
+#[async_trait]
+pub trait EmailTransport: Send + Sync {
+    async fn send(&self, message: Message) -> Result<(), DeliveryError>;
+}
+
+// keeps a pool of connections to the server rather than connecting for every email
+pub struct SmtpTransport {
+    transport: AsyncSmtpTransport<AsyncStd1Executor>,
+}
+
+#[async_trait]
+impl EmailTransport for SmtpTransport {
+    async fn send(&self, message: Message) -> Result<(), DeliveryError> {
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| DeliveryError {
+                message: format!("SMTP: {}", e),
+                permanent: e.is_permanent(),
+            })?;
+        Ok(())
+    }
+}
+
+pub struct SendmailTransport {
+    transport: AsyncSendmailTransport<AsyncStd1Executor>,
+}
+
+#[async_trait]
+impl EmailTransport for SendmailTransport {
+    async fn send(&self, message: Message) -> Result<(), DeliveryError> {
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| DeliveryError::transient(format!("sendmail: {}", e)))?;
+        Ok(())
+    }
+}
+
+// delivers into the `new` directory of a maildir, which mail clients and local
+// delivery agents can pick up
+pub struct MaildirTransport {
+    dir: PathBuf,
+}
+
+#[async_trait]
+impl EmailTransport for MaildirTransport {
+    async fn send(&self, message: Message) -> Result<(), DeliveryError> {
+        let name = format!("{}.{}.account-services", get_time_secs(), Ulid::new());
+        let tmp = self.dir.join("tmp").join(&name);
+        // written to tmp first, so readers never see a partial message
+        let result = async {
+            fs::write(&tmp, message.formatted()).await?;
+            fs::rename(&tmp, self.dir.join("new").join(&name)).await
+        }
+        .await;
+        result.map_err(|e| DeliveryError::transient(format!("maildir: {}", e)))
+    }
+}
+
+// writes each message to `<id>.eml` in the spool directory
+pub struct FileTransport {
+    transport: AsyncFileTransport<AsyncStd1Executor>,
+}
+
+#[async_trait]
+impl EmailTransport for FileTransport {
+    async fn send(&self, message: Message) -> Result<(), DeliveryError> {
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| DeliveryError::transient(format!("file: {}", e)))?;
+        Ok(())
+    }
+}
+
+static TRANSPORT: OnceCell<Option<Box<dyn EmailTransport>>> = OnceCell::new();
+
+fn get_spool_dir() -> PathBuf {
+    PathBuf::from(
+        EMAIL_SPOOL_DIR
+            .as_ref()
+            .expect("EMAIL_SPOOL_DIR must be set for the maildir and file transports"),
+    )
+}
+
+pub async fn init() {
+    let transport: Option<Box<dyn EmailTransport>> = match *EMAIL_TRANSPORT {
+        EmailBackend::Smtp => {
+            let server = SMTP_SERVER.as_ref().expect("SMTP_SERVER must be set");
+            let username = SMTP_USERNAME.as_ref().expect("SMTP_USERNAME must be set");
+            let password = SMTP_PASSWORD.as_ref().expect("SMTP_PASSWORD must be set");
+            let transport = AsyncSmtpTransport::<AsyncStd1Executor>::relay(server)
+                .expect("Failed to set SMTP server")
+                .credentials(Credentials::new(username.to_string(), password.to_string()))
+                .build();
+            Some(Box::new(SmtpTransport { transport }))
+        }
+        EmailBackend::Sendmail => {
+            let transport = match &*SENDMAIL_COMMAND {
+                Some(command) => AsyncSendmailTransport::new_with_command(command),
+                None => AsyncSendmailTransport::new(),
+            };
+            Some(Box::new(SendmailTransport { transport }))
+        }
+        EmailBackend::Maildir => {
+            let dir = get_spool_dir();
+            for sub in ["tmp", "new", "cur"] {
+                fs::create_dir_all(dir.join(sub))
+                    .await
+                    .expect("Failed to create maildir");
+            }
+            Some(Box::new(MaildirTransport { dir }))
+        }
+        EmailBackend::File => {
+            let dir = get_spool_dir();
+            fs::create_dir_all(&dir)
+                .await
+                .expect("Failed to create email spool directory");
+            Some(Box::new(FileTransport {
+                transport: AsyncFileTransport::new(dir),
+            }))
+        }
+        EmailBackend::Disabled => None,
+    };
+    if transport.is_some() && EMAIL_FROM.is_none() {
+        panic!("EMAIL_FROM must be set to send email");
+    }
+    info!("Using {:?} email transport", *EMAIL_TRANSPORT);
+    if TRANSPORT.set(transport).is_err() {
+        panic!("Failed to set email transport");
+    }
+}
+
+pub fn get_transport() -> Option<&'static dyn EmailTransport> {
+    TRANSPORT
+        .get()
+        .expect("Failed to get email transport")
+        .as_deref()
+}
+
+async fn deliver(transport: &dyn EmailTransport, email: &OutboxEmail) -> Result<(), DeliveryError> {
+    let rendered = templates::render(&email.template, &email.locale, &email.data)
+        .map_err(|e| DeliveryError::permanent(format!("template: {:?}", e)))?;
+    let from = EMAIL_FROM
+        .as_ref()
+        .ok_or_else(|| DeliveryError::transient("EMAIL_FROM is not set".to_string()))?;
+    let message = Message::builder()
+        .from(
+            from.parse()
+                .map_err(|e| DeliveryError::transient(format!("sender: {}", e)))?,
+        )
+        .to(email
+            .to
+            .parse()
+            .map_err(|e| DeliveryError::permanent(format!("recipient: {}", e)))?)
+        .subject(rendered.subject)
+        .multipart(MultiPart::alternative_plain_html(
+            rendered.text,
+            rendered.html,
+        ))
+        .map_err(|e| DeliveryError::permanent(format!("message: {}", e)))?;
+    transport.send(message).await
+}
+
+fn get_retry_delay(attempts: u32) -> u64 {
+    EMAIL_RETRY_BASE
+        .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+        .min(EMAIL_RETRY_MAX)
+}
+
+// sends every email in the outbox that is due
+pub async fn deliver_queued() {
+    let Some(transport) = get_transport() else {
+        return;
+    };
+    loop {
+        let email = match email_outbox::claim().await {
+            Ok(Some(email)) => email,
+            Ok(None) => break,
+            Err(e) => {
+                error!("Failed to read email outbox: {:?}", e);
+                break;
+            }
+        };
+        let result = match deliver(transport, &email).await {
+            Ok(()) => email_outbox::mark_sent(&email.id).await,
+            Err(e) => {
+                let retry_at = if e.permanent || email.attempts >= EMAIL_MAX_ATTEMPTS {
+                    error!(
+                        "Giving up on {} email {} after {} attempts: {}",
+                        email.template, email.id, email.attempts, e.message
+                    );
+                    None
+                } else {
+                    warn!(
+                        "Failed to send {} email {}, retrying: {}",
+                        email.template, email.id, e.message
+                    );
+                    Some(get_time_secs() + get_retry_delay(email.attempts))
+                };
+                email_outbox::mark_failed(&email.id, e.message, retry_at).await
+            }
+        };
+        if let Err(e) = result {
+            error!("Failed to update email {}: {:?}", email.id, e);
+        }
+    }
+}
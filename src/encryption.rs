@@ -0,0 +1,247 @@
+use aes_gcm::{Aes256Gcm, KeyInit};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_util::StreamExt;
+use log::{error, info};
+use mongodb::{
+    bson::{doc, to_bson, Document, Regex},
+    Collection,
+};
+use once_cell::sync::OnceCell;
+
+use crate::{
+    database::{
+        settings::{self, get_settings, EncryptionKey},
+        user, webhook,
+    },
+    environment::ENCRYPTION_KEYS,
+    errors::{Error, Result},
+    utilities::{decrypt, encrypt, random_number},
+};
+
+// Secrets are stored as `v{version}:{base64(nonce || ciphertext)}`. Values without
+// the prefix were written before encryption at rest and are read as plaintext.
+
+static KEYS: OnceCell<Vec<EncryptionKey>> = OnceCell::new();
+
+pub fn create_encryption_key(version: u32) -> EncryptionKey {
+    EncryptionKey {
+        version,
+        key: random_number(32),
+    }
+}
+
+fn parse_keys(keys: &str) -> Vec<EncryptionKey> {
+    keys.split(',')
+        .map(|k| {
+            let (version, key) = k
+                .trim()
+                .split_once(':')
+                .expect("ENCRYPTION_KEYS must be in the format version:key");
+            let key = STANDARD
+                .decode(key)
+                .expect("ENCRYPTION_KEYS must be base64 encoded");
+            if key.len() != 32 {
+                panic!("ENCRYPTION_KEYS must be 32 bytes long");
+            }
+            EncryptionKey {
+                version: version
+                    .parse()
+                    .expect("ENCRYPTION_KEYS versions must be numbers"),
+                key,
+            }
+        })
+        .collect()
+}
+
+async fn load_settings_keys() -> Result<Vec<EncryptionKey>> {
+    let settings = get_settings().await;
+    if !settings.encryption_keys.is_empty() {
+        return Ok(settings.encryption_keys);
+    }
+    // settings created before encryption keys existed
+    let key = to_bson(&create_encryption_key(1)).map_err(|_| Error::DatabaseError)?;
+    settings::get_collection()
+        .update_one(
+            doc! {
+                "$or": [
+                    { "encryption_keys": { "$exists": false } },
+                    { "encryption_keys": { "$size": 0 } },
+                ]
+            },
+            doc! {
+                "$set": {
+                    "encryption_keys": [key]
+                }
+            },
+        )
+        .await?;
+    Ok(get_settings().await.encryption_keys)
+}
+
+async fn get_keys() -> Result<&'static Vec<EncryptionKey>> {
+    if let Some(keys) = KEYS.get() {
+        return Ok(keys);
+    }
+    let keys = if let Some(keys) = &*ENCRYPTION_KEYS {
+        parse_keys(keys)
+    } else {
+        load_settings_keys().await?
+    };
+    if keys.is_empty() {
+        return Err(Error::EncryptionError);
+    }
+    Ok(KEYS.get_or_init(|| keys))
+}
+
+fn cipher(key: &EncryptionKey) -> Aes256Gcm {
+    Aes256Gcm::new_from_slice(&key.key).expect("Unexpected error: invalid encryption key")
+}
+
+async fn current_key() -> Result<&'static EncryptionKey> {
+    get_keys()
+        .await?
+        .iter()
+        .max_by_key(|k| k.version)
+        .ok_or(Error::EncryptionError)
+}
+
+pub async fn seal(plaintext: &str) -> Result<String> {
+    let key = current_key().await?;
+    let data = encrypt(plaintext.as_bytes().to_vec(), cipher(key));
+    Ok(format!("v{}:{}", key.version, STANDARD.encode(data)))
+}
+
+pub async fn open(stored: &str) -> Result<String> {
+    let Some((version, data)) = stored
+        .strip_prefix('v')
+        .and_then(|s| s.split_once(':'))
+        .and_then(|(v, d)| Some((v.parse::<u32>().ok()?, d)))
+    else {
+        return Ok(stored.to_string());
+    };
+    let key = get_keys()
+        .await?
+        .iter()
+        .find(|k| k.version == version)
+        .ok_or(Error::EncryptionError)?;
+    let data = STANDARD.decode(data).map_err(|_| Error::EncryptionError)?;
+    let plaintext = decrypt(data, Some(cipher(key)))?;
+    String::from_utf8(plaintext).map_err(|_| Error::EncryptionError)
+}
+
+// opens a secret and seals it again under the current key
+async fn reseal(stored: &str) -> Result<String> {
+    seal(&open(stored).await?).await
+}
+
+// re-seals `field` on each document in `collection` that isn't under the current key;
+// returns how many were re-sealed
+async fn rewrap_field(collection: Collection<Document>, field: &str, current: &Regex) -> u64 {
+    let cursor = collection
+        .find(doc! {
+            field: {
+                "$type": "string",
+                "$not": current.clone(),
+            }
+        })
+        .await;
+    let Ok(mut cursor) = cursor else {
+        return 0;
+    };
+    let mut count = 0;
+    while let Some(Ok(document)) = cursor.next().await {
+        let (Ok(id), Ok(stored)) = (document.get_str("id"), document.get_str(field)) else {
+            continue;
+        };
+        let Ok(sealed) = reseal(stored).await else {
+            error!("Failed to re-wrap {} of {}", field, id);
+            continue;
+        };
+        let result = collection
+            .update_one(
+                doc! {
+                    "id": id,
+                    field: stored,
+                },
+                doc! {
+                    "$set": {
+                        field: sealed
+                    }
+                },
+            )
+            .await;
+        if result.is_ok() {
+            count += 1;
+        }
+    }
+    count
+}
+
+// signing keys live in an array in the settings document
+async fn rewrap_signing_keys(prefix: &str) -> u64 {
+    let mut count = 0;
+    for key in get_settings().await.signing_keys {
+        if key.private_key.starts_with(prefix) {
+            continue;
+        }
+        let Ok(sealed) = reseal(&key.private_key).await else {
+            error!("Failed to re-wrap signing key {}", key.id);
+            continue;
+        };
+        let result = settings::get_collection()
+            .update_one(
+                doc! {
+                    "signing_keys": {
+                        "$elemMatch": {
+                            "id": &key.id,
+                            "private_key": &key.private_key,
+                        }
+                    }
+                },
+                doc! {
+                    "$set": {
+                        "signing_keys.$.private_key": sealed
+                    }
+                },
+            )
+            .await;
+        if result.is_ok() {
+            count += 1;
+        }
+    }
+    count
+}
+
+// Re-encrypts secrets written with an older key version (or in plaintext) under the
+// current key: MFA secrets, webhook secrets and signing keys. MFA setups in progress
+// are left alone, as their flows expire long before a key would be retired.
+pub async fn rewrap_secrets() {
+    let key = match current_key().await {
+        Ok(key) => key,
+        Err(_) => {
+            error!("No encryption key is configured");
+            return;
+        }
+    };
+    let prefix = format!("v{}:", key.version);
+    let current = Regex {
+        pattern: format!("^{}", prefix),
+        options: String::new(),
+    };
+    let mut count = rewrap_field(
+        user::get_collection().clone_with_type(),
+        "mfa_secret",
+        &current,
+    )
+    .await;
+    count += rewrap_field(
+        webhook::get_collection().clone_with_type(),
+        "secret",
+        &current,
+    )
+    .await;
+    count += rewrap_signing_keys(&prefix).await;
+    if count > 0 {
+        info!("Re-wrapped {} secrets", count);
+    }
+}
@@ -2,15 +2,54 @@ use std::env;
 
 use lazy_static::lazy_static;
 
+use crate::{
+    captcha::CaptchaProvider, database::settings::KeyAlgorithm, email::EmailBackend,
+    flows::FlowBackend,
+};
+
 lazy_static! {
     pub static ref MONGODB_URI: String = env::var("MONGODB_URI").expect("MONGODB_URI must be set");
     pub static ref MONGODB_DATABASE: String =
         env::var("MONGODB_DATABASE").expect("MONGODB_DATABASE must be set");
     pub static ref CDN_MONGODB_DATABASE: String =
         env::var("CDN_MONGODB_DATABASE").expect("CDN_MONGODB_DATABASE must be set");
-    pub static ref JWT_SECRET: String = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    pub static ref HCAPTCHA_SECRET: String =
-        env::var("HCAPTCHA_SECRET").expect("HCAPTCHA_SECRET must be set");
+    // only used to verify HS256 tokens issued before asymmetric signing
+    pub static ref JWT_SECRET: Option<String> = env::var("JWT_SECRET").ok();
+    pub static ref JWT_ALGORITHM: KeyAlgorithm = match env::var("JWT_ALGORITHM").as_deref() {
+        Ok("ES256") | Err(_) => KeyAlgorithm::ES256,
+        Ok("EdDSA") => KeyAlgorithm::EdDSA,
+        Ok(_) => panic!("JWT_ALGORITHM must be ES256 or EdDSA"),
+    };
+    // comma-separated `version:base64 key` pairs; keys are stored in settings if unset
+    pub static ref ENCRYPTION_KEYS: Option<String> = env::var("ENCRYPTION_KEYS").ok();
+    pub static ref FLOW_STORE: FlowBackend = match env::var("FLOW_STORE").as_deref() {
+        Ok("memory") | Err(_) => FlowBackend::Memory,
+        Ok("mongodb") => FlowBackend::MongoDB,
+        Ok(_) => panic!("FLOW_STORE must be memory or mongodb"),
+    };
+    // path to a MaxMind GeoLite2/GeoIP2 City database; locations are omitted if unset
+    pub static ref GEOIP_DATABASE: Option<String> = env::var("GEOIP_DATABASE").ok();
+    pub static ref CAPTCHA_PROVIDER: CaptchaProvider = match env::var("CAPTCHA_PROVIDER").as_deref() {
+        Ok("hcaptcha") | Err(_) => CaptchaProvider::HCaptcha,
+        Ok("turnstile") => CaptchaProvider::Turnstile,
+        Ok("recaptcha") => CaptchaProvider::ReCaptcha,
+        Ok("proof_of_work") => CaptchaProvider::ProofOfWork,
+        Ok("always_pass") => CaptchaProvider::AlwaysPass,
+        Ok("always_fail") => CaptchaProvider::AlwaysFail,
+        Ok(_) => panic!("CAPTCHA_PROVIDER must be hcaptcha, turnstile, recaptcha, proof_of_work, always_pass or always_fail"),
+    };
+    pub static ref CAPTCHA_SECRET: Option<String> =
+        env::var("CAPTCHA_SECRET").or_else(|_| env::var("HCAPTCHA_SECRET")).ok();
+    // handed to the frontend so it can render the provider's widget
+    pub static ref CAPTCHA_SITE_KEY: Option<String> = env::var("CAPTCHA_SITE_KEY").ok();
+    // scored responses (reCAPTCHA v3) below this are rejected
+    pub static ref CAPTCHA_MIN_SCORE: f64 = env::var("CAPTCHA_MIN_SCORE")
+        .map(|score| score.parse().expect("CAPTCHA_MIN_SCORE must be a number"))
+        .unwrap_or(0.5);
+    // leading zero bits required in proof of work solutions
+    pub static ref CAPTCHA_POW_DIFFICULTY: u32 = env::var("CAPTCHA_POW_DIFFICULTY")
+        .map(|difficulty| difficulty.parse().expect("CAPTCHA_POW_DIFFICULTY must be a number"))
+        .unwrap_or(20);
     pub static ref CORS_ORIGINS: Vec<String> = env::var("CORS_ORIGINS")
         .expect("CORS_ORIGINS must be set")
         .split(',')
@@ -20,11 +59,36 @@ lazy_static! {
     pub static ref SMTP_USERNAME: Option<String> = env::var("SMTP_USERNAME").ok();
     pub static ref SMTP_PASSWORD: Option<String> = env::var("SMTP_PASSWORD").ok();
     pub static ref SMTP_SERVER: Option<String> = env::var("SMTP_SERVER").ok();
-    pub static ref SMTP_FROM: Option<String> = env::var("SMTP_FROM").ok();
-    pub static ref SMTP_ENABLED: bool = SMTP_USERNAME.is_some()
-        && SMTP_PASSWORD.is_some()
-        && SMTP_SERVER.is_some()
-        && SMTP_FROM.is_some();
+    // defaults to smtp when the SMTP variables are set, so existing deployments keep working
+    pub static ref EMAIL_TRANSPORT: EmailBackend = match env::var("EMAIL_TRANSPORT").as_deref() {
+        Ok("smtp") => EmailBackend::Smtp,
+        Ok("sendmail") => EmailBackend::Sendmail,
+        Ok("maildir") => EmailBackend::Maildir,
+        Ok("file") => EmailBackend::File,
+        Ok("none") => EmailBackend::Disabled,
+        Ok(_) => panic!("EMAIL_TRANSPORT must be smtp, sendmail, maildir, file or none"),
+        Err(_) if SMTP_USERNAME.is_some()
+            && SMTP_PASSWORD.is_some()
+            && SMTP_SERVER.is_some()
+            && EMAIL_FROM.is_some() => EmailBackend::Smtp,
+        Err(_) => EmailBackend::Disabled,
+    };
+    pub static ref EMAIL_FROM: Option<String> =
+        env::var("EMAIL_FROM").or_else(|_| env::var("SMTP_FROM")).ok();
+    // where the maildir and file transports write messages
+    pub static ref EMAIL_SPOOL_DIR: Option<String> = env::var("EMAIL_SPOOL_DIR").ok();
+    pub static ref SENDMAIL_COMMAND: Option<String> = env::var("SENDMAIL_COMMAND").ok();
+    pub static ref EMAIL_ENABLED: bool = *EMAIL_TRANSPORT != EmailBackend::Disabled;
+    pub static ref EMAIL_TEMPLATES: String =
+        env::var("EMAIL_TEMPLATES").unwrap_or("templates/email".to_string());
+    // files here replace the bundled template at the same relative path
+    pub static ref EMAIL_TEMPLATE_OVERRIDES: Option<String> =
+        env::var("EMAIL_TEMPLATE_OVERRIDES").ok();
+    // seconds between requesting deletion and the account being purged
+    pub static ref DELETION_GRACE_PERIOD: u64 = env::var("DELETION_GRACE_DAYS")
+        .map(|days| days.parse::<u64>().expect("DELETION_GRACE_DAYS must be a number"))
+        .unwrap_or(14)
+        * 86400;
     pub static ref PUBLIC_ROOT: String = env::var("PUBLIC_ROOT").expect("PUBLIC_ROOT must be set");
     pub static ref SERVICE_NAME: String =
         env::var("SERVICE_NAME").expect("SERVICE_NAME must be set");
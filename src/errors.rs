@@ -14,6 +14,7 @@ pub enum Error {
     InvalidToken,
 
     DatabaseError,
+    EncryptionError,
 
     InvalidUsername,
     UsernameAlreadyTaken,
@@ -22,23 +23,60 @@ pub enum Error {
     UserMismatch,
 
     InvalidEmail,
+    InvalidLocale,
     DisplayNameTooLong,
     DescriptionTooLong,
     WebsiteTooLong,
+    InvalidAvatar,
+    AvatarTooLarge,
 
     CredentialError,
     IncorrectCode,
+    MfaNotEnabled,
 
     SessionExpired,
+    SessionNotFound,
+    AccountSuspended {
+        reason: String,
+        until: Option<u64>,
+    },
+    AccountLocked,
+    AccountTemporarilyLocked {
+        until: u64,
+    },
+    TooManyAttempts {
+        retry_after: u64,
+    },
+
+    ExportNotFound,
+    ExportNotReady,
+    ExportFailed,
 
     IpMissing,
 
     InvalidCaptcha,
+    CaptchaRequired,
     InternalCaptchaError,
 
     InternalEmailError,
     EmailMisconfigured,
 
+    WebhookNotFound,
+    InvalidWebhookUrl,
+
+    MachineClientNotFound,
+    InvalidPublicKey,
+    TooManyUsers,
+
+    InvalidClient,
+    InvalidRedirectUri,
+    InvalidScope,
+    InvalidGrant,
+    UnsupportedResponseType,
+    UnsupportedGrantType,
+
+    MissingPermission,
+
     RateLimited {
         limit: u64,
         remaining: u64,
@@ -61,6 +99,7 @@ impl ResponseError for Error {
             Error::InvalidToken => actix_web::http::StatusCode::UNAUTHORIZED,
 
             Error::DatabaseError => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            Error::EncryptionError => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
 
             Error::InvalidUsername => actix_web::http::StatusCode::BAD_REQUEST,
             Error::UsernameAlreadyTaken => actix_web::http::StatusCode::CONFLICT,
@@ -69,20 +108,49 @@ impl ResponseError for Error {
             Error::UserMismatch => actix_web::http::StatusCode::UNAUTHORIZED,
 
             Error::InvalidEmail => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::InvalidLocale => actix_web::http::StatusCode::BAD_REQUEST,
             Error::DisplayNameTooLong => actix_web::http::StatusCode::BAD_REQUEST,
             Error::DescriptionTooLong => actix_web::http::StatusCode::BAD_REQUEST,
             Error::WebsiteTooLong => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::InvalidAvatar => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::AvatarTooLarge => actix_web::http::StatusCode::PAYLOAD_TOO_LARGE,
 
             Error::CredentialError => actix_web::http::StatusCode::UNAUTHORIZED,
             Error::IncorrectCode => actix_web::http::StatusCode::UNAUTHORIZED,
+            Error::MfaNotEnabled => actix_web::http::StatusCode::BAD_REQUEST,
 
             Error::SessionExpired => actix_web::http::StatusCode::UNAUTHORIZED,
+            Error::SessionNotFound => actix_web::http::StatusCode::NOT_FOUND,
+            Error::AccountSuspended { .. } => actix_web::http::StatusCode::FORBIDDEN,
+            Error::AccountLocked => actix_web::http::StatusCode::FORBIDDEN,
+            Error::AccountTemporarilyLocked { .. } => actix_web::http::StatusCode::FORBIDDEN,
+            Error::TooManyAttempts { .. } => actix_web::http::StatusCode::TOO_MANY_REQUESTS,
+
+            Error::ExportNotFound => actix_web::http::StatusCode::NOT_FOUND,
+            Error::ExportNotReady => actix_web::http::StatusCode::CONFLICT,
+            Error::ExportFailed => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
 
             Error::IpMissing => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
 
             Error::InvalidCaptcha => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::CaptchaRequired => actix_web::http::StatusCode::BAD_REQUEST,
             Error::InternalCaptchaError => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
 
+            Error::WebhookNotFound => actix_web::http::StatusCode::NOT_FOUND,
+            Error::InvalidWebhookUrl => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::MachineClientNotFound => actix_web::http::StatusCode::NOT_FOUND,
+            Error::InvalidPublicKey => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::TooManyUsers => actix_web::http::StatusCode::BAD_REQUEST,
+
+            Error::InvalidClient => actix_web::http::StatusCode::UNAUTHORIZED,
+            Error::InvalidRedirectUri => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::InvalidScope => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::InvalidGrant => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::UnsupportedResponseType => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::UnsupportedGrantType => actix_web::http::StatusCode::BAD_REQUEST,
+
+            Error::MissingPermission => actix_web::http::StatusCode::FORBIDDEN,
+
             Error::RateLimited { .. } => actix_web::http::StatusCode::TOO_MANY_REQUESTS,
 
             Error::InternalEmailError => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
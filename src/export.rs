@@ -0,0 +1,207 @@
+use futures_util::TryStreamExt;
+use log::error;
+use mongodb::bson::{doc, DateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    database::{
+        audit_event,
+        export::{self, DataExport},
+        files::File,
+        passkey, profile,
+        profile::UserProfile,
+        session::{self, UserAgent},
+        user::{self, AccountStatus, User},
+    },
+    environment::PUBLIC_ROOT,
+    errors::{Error, Result},
+    routes::security_log::AuditEventEntry,
+    utilities::{get_time_secs, send_template_email},
+};
+
+// A user can download a copy of everything the account service stores about them.
+// Archives are assembled in the background and kept for EXPORT_LIFETIME; password
+// data, the MFA secret, session tokens and passkey public keys are left out, as
+// they are of no use outside the service.
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Archive {
+    exported_at: u64,
+    account: ArchivedAccount,
+    profile: Option<ArchivedProfile>,
+    // avatar metadata from the CDN
+    avatar: Option<File>,
+    sessions: Vec<ArchivedSession>,
+    passkeys: Vec<ArchivedPasskey>,
+    audit_events: Vec<AuditEventEntry>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedAccount {
+    id: String,
+    email: String,
+    username: String,
+    mfa_enabled: bool,
+    platform_administrator: bool,
+    status: AccountStatus,
+    locale: Option<String>,
+    deletion_scheduled_for: Option<u64>,
+}
+
+impl From<User> for ArchivedAccount {
+    fn from(user: User) -> Self {
+        ArchivedAccount {
+            id: user.id,
+            email: user.email,
+            username: user.username,
+            mfa_enabled: user.mfa_enabled,
+            platform_administrator: user.platform_administrator,
+            status: user.status,
+            locale: user.locale,
+            deletion_scheduled_for: user.deletion.map(|deletion| deletion.purge_at),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedProfile {
+    display_name: String,
+    description: String,
+    website: String,
+    avatar: Option<String>,
+}
+
+impl From<UserProfile> for ArchivedProfile {
+    fn from(profile: UserProfile) -> Self {
+        ArchivedProfile {
+            display_name: profile.display_name,
+            description: profile.description,
+            website: profile.website,
+            avatar: profile.avatar,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedSession {
+    id: String,
+    friendly_name: String,
+    persistent: bool,
+    created_at: u64,
+    last_used_at: u64,
+    expires_at: u64,
+    ip: Option<String>,
+    user_agent: Option<UserAgent>,
+    location: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedPasskey {
+    id: String,
+    friendly_name: String,
+    credential_id: String,
+}
+
+async fn build(user_id: &str) -> Result<Archive> {
+    let user = user::get_collection()
+        .find_one(doc! {
+            "id": user_id
+        })
+        .await?
+        .ok_or(Error::UserNotFound)?;
+    let profile = profile::get_collection()
+        .find_one(doc! {
+            "id": user_id
+        })
+        .await?;
+    let avatar = match profile.as_ref().and_then(|profile| profile.avatar.as_ref()) {
+        Some(avatar) if avatar != "default" => File::get(avatar).await.ok(),
+        _ => None,
+    };
+    let sessions = session::get_collection()
+        .find(doc! {
+            "user_id": user_id
+        })
+        .await?
+        .map_ok(|session| ArchivedSession {
+            id: session.id,
+            friendly_name: session.friendly_name,
+            persistent: session.persistent,
+            created_at: session.created_at,
+            last_used_at: session.last_used_at,
+            expires_at: session.expires_at,
+            ip: session.ip,
+            user_agent: session.user_agent,
+            location: session.location,
+        })
+        .try_collect()
+        .await?;
+    let passkeys = passkey::get_collection()
+        .find(doc! {
+            "user_id": user_id
+        })
+        .await?
+        .map_ok(|passkey| ArchivedPasskey {
+            id: passkey.id,
+            friendly_name: passkey.friendly_name,
+            credential_id: passkey.credential_id,
+        })
+        .try_collect()
+        .await?;
+    let audit_events = audit_event::get_collection()
+        .find(doc! {
+            "target_id": user_id
+        })
+        .sort(doc! { "id": 1 })
+        .await?
+        .map_ok(AuditEventEntry::from)
+        .try_collect()
+        .await?;
+    Ok(Archive {
+        exported_at: get_time_secs(),
+        account: user.into(),
+        profile: profile.map(ArchivedProfile::from),
+        avatar,
+        sessions,
+        passkeys,
+        audit_events,
+    })
+}
+
+async fn store(export: &DataExport) -> Result<DateTime> {
+    let archive = build(&export.user_id).await?;
+    let data = serde_json::to_vec(&archive).map_err(|_| Error::DatabaseError)?;
+    export::mark_ready(export, &data).await
+}
+
+// assembles the archive and emails the user a link to it; spawned, as reading
+// every collection can take a while
+pub async fn prepare(export: DataExport, user: User, locale: String) {
+    let expires_at = match store(&export).await {
+        Ok(expires_at) => expires_at,
+        Err(e) => {
+            error!("Failed to export data for {}: {:?}", user.id, e);
+            if let Err(e) = export::mark_failed(&export.id).await {
+                error!("Failed to mark export {} as failed: {:?}", export.id, e);
+            }
+            return;
+        }
+    };
+    send_template_email(
+        user.email,
+        "export_ready",
+        locale,
+        json!({
+            "url": format!("{}/export?id={}", &*PUBLIC_ROOT, export.id),
+            "expires_at": expires_at.try_to_rfc3339_string().unwrap_or_default(),
+        }),
+    )
+    .await
+    .ok();
+}
@@ -0,0 +1,301 @@
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use async_trait::async_trait;
+use dashmap::DashMap;
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use log::info;
+use mongodb::bson::{self, doc, DateTime, Document};
+use once_cell::sync::OnceCell;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    database::flow::{self, FlowEntry},
+    environment::FLOW_STORE,
+    errors::{Error, Result},
+    utilities::get_time_secs,
+};
+
+// State for multi-stage flows (logins, registrations, escalations, ...) lives in a
+// FlowStore so that any replica can continue a flow started on another. Each flow
+// declares its lifetime once; expired entries are never returned and are swept
+// periodically by cleanup::run.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowBackend {
+    Memory,
+    MongoDB,
+}
+
+#[derive(Clone, Debug)]
+pub struct FlowRecord {
+    pub data: Document,
+    pub expires_at: u64,
+}
+
+#[async_trait]
+pub trait FlowStore: Send + Sync {
+    async fn insert(&self, flow: &str, key: &str, record: FlowRecord) -> Result<()>;
+    async fn get(&self, flow: &str, key: &str) -> Result<Option<FlowRecord>>;
+    async fn remove(&self, flow: &str, key: &str) -> Result<Option<FlowRecord>>;
+    // returns the number of entries removed per flow
+    async fn purge_expired(&self, now: u64) -> Result<HashMap<String, u64>>;
+}
+
+#[derive(Default)]
+pub struct MemoryFlowStore {
+    entries: DashMap<(String, String), FlowRecord>,
+}
+
+#[async_trait]
+impl FlowStore for MemoryFlowStore {
+    async fn insert(&self, flow: &str, key: &str, record: FlowRecord) -> Result<()> {
+        self.entries
+            .insert((flow.to_string(), key.to_string()), record);
+        Ok(())
+    }
+
+    async fn get(&self, flow: &str, key: &str) -> Result<Option<FlowRecord>> {
+        Ok(self
+            .entries
+            .get(&(flow.to_string(), key.to_string()))
+            .map(|entry| entry.value().clone()))
+    }
+
+    async fn remove(&self, flow: &str, key: &str) -> Result<Option<FlowRecord>> {
+        Ok(self
+            .entries
+            .remove(&(flow.to_string(), key.to_string()))
+            .map(|(_, record)| record))
+    }
+
+    async fn purge_expired(&self, now: u64) -> Result<HashMap<String, u64>> {
+        let mut purged = HashMap::new();
+        self.entries.retain(|(flow, _), record| {
+            if record.expires_at > now {
+                return true;
+            }
+            *purged.entry(flow.clone()).or_insert(0) += 1;
+            false
+        });
+        Ok(purged)
+    }
+}
+
+pub struct MongoFlowStore;
+
+impl From<FlowEntry> for FlowRecord {
+    fn from(entry: FlowEntry) -> Self {
+        FlowRecord {
+            data: entry.data,
+            expires_at: (entry.expires_at.timestamp_millis() / 1000) as u64,
+        }
+    }
+}
+
+#[async_trait]
+impl FlowStore for MongoFlowStore {
+    async fn insert(&self, flow: &str, key: &str, record: FlowRecord) -> Result<()> {
+        flow::get_collection()
+            .insert_one(FlowEntry {
+                flow: flow.to_string(),
+                key: key.to_string(),
+                data: record.data,
+                expires_at: DateTime::from_millis((record.expires_at * 1000) as i64),
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn get(&self, flow: &str, key: &str) -> Result<Option<FlowRecord>> {
+        let entry = flow::get_collection()
+            .find_one(doc! {
+                "flow": flow,
+                "key": key
+            })
+            .await?;
+        Ok(entry.map(FlowRecord::from))
+    }
+
+    async fn remove(&self, flow: &str, key: &str) -> Result<Option<FlowRecord>> {
+        let entry = flow::get_collection()
+            .find_one_and_delete(doc! {
+                "flow": flow,
+                "key": key
+            })
+            .await?;
+        Ok(entry.map(FlowRecord::from))
+    }
+
+    async fn purge_expired(&self, now: u64) -> Result<HashMap<String, u64>> {
+        // the TTL index would remove these eventually, but not in a way we can count
+        let collection = flow::get_collection();
+        let filter = doc! {
+            "expires_at": { "$lte": DateTime::from_millis((now * 1000) as i64) }
+        };
+        let mut cursor = collection
+            .aggregate(vec![
+                doc! { "$match": filter.clone() },
+                doc! { "$group": { "_id": "$flow", "count": { "$sum": 1 } } },
+            ])
+            .await?;
+        let mut purged = HashMap::new();
+        while let Some(Ok(group)) = cursor.next().await {
+            if let (Ok(flow), Ok(count)) = (group.get_str("_id"), group.get_i32("count")) {
+                purged.insert(flow.to_string(), count as u64);
+            }
+        }
+        collection.delete_many(filter).await?;
+        Ok(purged)
+    }
+}
+
+static STORE: OnceCell<Box<dyn FlowStore>> = OnceCell::new();
+
+pub async fn init() {
+    let store: Box<dyn FlowStore> = match *FLOW_STORE {
+        FlowBackend::Memory => Box::new(MemoryFlowStore::default()),
+        FlowBackend::MongoDB => {
+            flow::create_indexes()
+                .await
+                .expect("Failed to create flow indexes");
+            Box::new(MongoFlowStore)
+        }
+    };
+    info!("Using {:?} flow store", *FLOW_STORE);
+    if STORE.set(store).is_err() {
+        panic!("Failed to set flow store");
+    }
+}
+
+pub fn get_store() -> &'static dyn FlowStore {
+    STORE.get().expect("Failed to get flow store").as_ref()
+}
+
+#[derive(Default)]
+struct FlowCounters {
+    ttl: u64,
+    created: AtomicU64,
+    completed: AtomicU64,
+    expired: AtomicU64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlowMetrics {
+    pub name: String,
+    pub ttl: u64,
+    pub created: u64,
+    pub completed: u64,
+    pub expired: u64,
+}
+
+lazy_static! {
+    // counters are per process; flows register themselves on first use
+    static ref REGISTRY: DashMap<&'static str, FlowCounters> = DashMap::new();
+}
+
+fn count(name: &'static str, ttl: u64, counter: fn(&FlowCounters) -> &AtomicU64) {
+    let counters = REGISTRY.entry(name).or_insert_with(|| FlowCounters {
+        ttl,
+        ..Default::default()
+    });
+    counter(&counters).fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn get_metrics() -> Vec<FlowMetrics> {
+    let mut metrics = REGISTRY
+        .iter()
+        .map(|entry| FlowMetrics {
+            name: entry.key().to_string(),
+            ttl: entry.ttl,
+            created: entry.created.load(Ordering::Relaxed),
+            completed: entry.completed.load(Ordering::Relaxed),
+            expired: entry.expired.load(Ordering::Relaxed),
+        })
+        .collect::<Vec<_>>();
+    metrics.sort_by(|a, b| a.name.cmp(&b.name));
+    metrics
+}
+
+pub async fn purge_expired() -> Result<u64> {
+    let purged = get_store().purge_expired(get_time_secs()).await?;
+    let mut total = 0;
+    for (name, count) in purged {
+        total += count;
+        // only flows this process has used are tracked
+        if let Some(counters) = REGISTRY.get(name.as_str()) {
+            counters.expired.fetch_add(count, Ordering::Relaxed);
+        }
+    }
+    Ok(total)
+}
+
+// a typed view over one kind of flow in the store
+pub struct Flow<T> {
+    name: &'static str,
+    ttl: u64,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Serialize + DeserializeOwned> Flow<T> {
+    pub const fn new(name: &'static str, ttl: u64) -> Self {
+        Self {
+            name,
+            ttl,
+            _marker: PhantomData,
+        }
+    }
+
+    fn decode(&self, record: FlowRecord) -> Result<T> {
+        bson::from_document(record.data).map_err(|_| Error::DatabaseError)
+    }
+
+    pub async fn insert(&self, key: &str, value: &T) -> Result<()> {
+        let data = bson::to_document(value).map_err(|_| Error::DatabaseError)?;
+        get_store()
+            .insert(
+                self.name,
+                key,
+                FlowRecord {
+                    data,
+                    expires_at: get_time_secs() + self.ttl,
+                },
+            )
+            .await?;
+        count(self.name, self.ttl, |c| &c.created);
+        Ok(())
+    }
+
+    // returns None if the flow does not exist or has expired
+    pub async fn get(&self, key: &str) -> Result<Option<T>> {
+        let Some(entry) = get_store().get(self.name, key).await? else {
+            return Ok(None);
+        };
+        if entry.expires_at <= get_time_secs() {
+            // another request may have removed it first
+            if get_store().remove(self.name, key).await?.is_some() {
+                count(self.name, self.ttl, |c| &c.expired);
+            }
+            return Ok(None);
+        }
+        self.decode(entry).map(Some)
+    }
+
+    // removes the flow, returning its state if it had not expired
+    pub async fn complete(&self, key: &str) -> Result<Option<T>> {
+        let Some(entry) = get_store().remove(self.name, key).await? else {
+            return Ok(None);
+        };
+        if entry.expires_at <= get_time_secs() {
+            count(self.name, self.ttl, |c| &c.expired);
+            return Ok(None);
+        }
+        count(self.name, self.ttl, |c| &c.completed);
+        self.decode(entry).map(Some)
+    }
+}
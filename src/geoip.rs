@@ -0,0 +1,48 @@
+use std::net::IpAddr;
+
+use log::{error, info};
+use maxminddb::{geoip2, Reader};
+use once_cell::sync::OnceCell;
+
+use crate::environment::GEOIP_DATABASE;
+
+static READER: OnceCell<Option<Reader<Vec<u8>>>> = OnceCell::new();
+
+fn get_reader() -> Option<&'static Reader<Vec<u8>>> {
+    READER
+        .get_or_init(|| {
+            let path = GEOIP_DATABASE.as_ref()?;
+            match Reader::open_readfile(path) {
+                Ok(reader) => {
+                    info!("Loaded GeoIP database from {}", path);
+                    Some(reader)
+                }
+                Err(e) => {
+                    error!("Failed to load GeoIP database: {}", e);
+                    None
+                }
+            }
+        })
+        .as_ref()
+}
+
+// returns a human-readable location such as "Toronto, Canada"
+pub fn lookup(ip: &str) -> Option<String> {
+    let reader = get_reader()?;
+    let ip = ip.parse::<IpAddr>().ok()?;
+    let city = reader.lookup::<geoip2::City>(ip).ok()?;
+    let city_name = city
+        .city
+        .and_then(|c| c.names)
+        .and_then(|n| n.get("en").copied());
+    let country_name = city
+        .country
+        .and_then(|c| c.names)
+        .and_then(|n| n.get("en").copied());
+    match (city_name, country_name) {
+        (Some(city), Some(country)) => Some(format!("{}, {}", city, country)),
+        (None, Some(country)) => Some(country.to_string()),
+        (Some(city), None) => Some(city.to_string()),
+        (None, None) => None,
+    }
+}
@@ -0,0 +1,157 @@
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::ClientJwt,
+    constants::{CLIENT_ASSERTION_MAX_AGE, MACHINE_TOKEN_LIFETIME},
+    database::machine_client::{self, MachineClient, MachineScope},
+    environment::PUBLIC_ROOT,
+    errors::{Error, Result},
+    flows::Flow,
+    signing,
+    utilities::{get_time_millis, get_time_secs, hash_secret},
+};
+
+// Backend services authenticate as machine clients rather than impersonating a
+// user. A client exchanges its secret, or a JWT assertion signed with its private
+// key (RFC 7523), for an access token with the client_credentials grant, then sends
+// that token as a bearer token to the service routes its scopes allow.
+
+pub const CLIENT_ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UsedAssertion {
+    pub client_id: String,
+}
+
+// assertion IDs already exchanged, kept until the assertions have expired
+pub static USED_ASSERTIONS: Flow<UsedAssertion> =
+    Flow::new("client_assertion", CLIENT_ASSERTION_MAX_AGE);
+
+#[derive(Deserialize)]
+struct AssertionClaims {
+    sub: String,
+    exp: u64,
+    jti: String,
+}
+
+async fn get_client(id: &str) -> Result<MachineClient> {
+    machine_client::get_collection()
+        .find_one(doc! {
+            "id": id
+        })
+        .await?
+        .ok_or(Error::InvalidClient)
+}
+
+pub async fn authenticate_secret(id: &str, secret: &str) -> Result<MachineClient> {
+    let client = get_client(id).await?;
+    if client.secret.as_deref() != Some(hash_secret(secret).as_str()) {
+        return Err(Error::InvalidClient);
+    }
+    Ok(client)
+}
+
+// no shared-secret algorithms, as the service would then hold the client's key
+fn get_decoding_key(algorithm: Algorithm, pem: &str) -> Result<DecodingKey> {
+    let key = match algorithm {
+        Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(pem.as_bytes()),
+        Algorithm::EdDSA => DecodingKey::from_ed_pem(pem.as_bytes()),
+        Algorithm::RS256
+        | Algorithm::RS384
+        | Algorithm::RS512
+        | Algorithm::PS256
+        | Algorithm::PS384
+        | Algorithm::PS512 => DecodingKey::from_rsa_pem(pem.as_bytes()),
+        _ => return Err(Error::InvalidClient),
+    };
+    key.map_err(|_| Error::InvalidClient)
+}
+
+pub fn validate_public_key(pem: &str) -> Result<()> {
+    let valid = DecodingKey::from_ec_pem(pem.as_bytes()).is_ok()
+        || DecodingKey::from_ed_pem(pem.as_bytes()).is_ok()
+        || DecodingKey::from_rsa_pem(pem.as_bytes()).is_ok();
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidPublicKey)
+    }
+}
+
+pub async fn authenticate_assertion(assertion: &str) -> Result<MachineClient> {
+    let header = decode_header(assertion).map_err(|_| Error::InvalidClient)?;
+    // the client is only known from the claims, which are checked once its key is found
+    let mut unverified = Validation::new(header.alg);
+    unverified.insecure_disable_signature_validation();
+    unverified.validate_exp = false;
+    unverified.validate_aud = false;
+    let claims = decode::<AssertionClaims>(assertion, &DecodingKey::from_secret(&[]), &unverified)
+        .map_err(|_| Error::InvalidClient)?
+        .claims;
+    let client = get_client(&claims.sub).await?;
+    let Some(public_key) = &client.public_key else {
+        return Err(Error::InvalidClient);
+    };
+    let mut validation = Validation::new(header.alg);
+    validation.set_required_spec_claims(&["exp", "iss", "sub", "aud", "jti"]);
+    validation.set_issuer(&[&client.id]);
+    validation.set_audience(&[
+        format!("{}/api/oauth/token", &*PUBLIC_ROOT),
+        PUBLIC_ROOT.to_string(),
+    ]);
+    let claims = decode::<AssertionClaims>(
+        assertion,
+        &get_decoding_key(header.alg, public_key)?,
+        &validation,
+    )
+    .map_err(|_| Error::InvalidClient)?
+    .claims;
+    if claims.sub != client.id {
+        return Err(Error::InvalidClient);
+    }
+    // longer-lived assertions couldn't be remembered until they expire
+    if claims.exp > get_time_secs() + CLIENT_ASSERTION_MAX_AGE {
+        return Err(Error::InvalidClient);
+    }
+    let key = format!("{}:{}", client.id, claims.jti);
+    if USED_ASSERTIONS.get(&key).await?.is_some() {
+        return Err(Error::InvalidClient);
+    }
+    USED_ASSERTIONS
+        .insert(
+            &key,
+            &UsedAssertion {
+                client_id: client.id.clone(),
+            },
+        )
+        .await?;
+    Ok(client)
+}
+
+// every scope the client has when none are requested
+pub fn grant_scopes(client: &MachineClient, scope: Option<&str>) -> Result<Vec<MachineScope>> {
+    let Some(scope) = scope.filter(|scope| !scope.trim().is_empty()) else {
+        return Ok(client.scopes.clone());
+    };
+    scope
+        .split_whitespace()
+        .map(|scope| {
+            MachineScope::parse(scope)
+                .filter(|scope| client.scopes.contains(scope))
+                .ok_or(Error::InvalidScope)
+        })
+        .collect()
+}
+
+pub async fn create_access_token(client_id: &str, scopes: Vec<MachineScope>) -> Result<String> {
+    let millis = get_time_millis();
+    signing::encode(&ClientJwt {
+        client_id: client_id.to_string(),
+        scopes,
+        issued_at: millis,
+        expires_at: millis + (MACHINE_TOKEN_LIFETIME * 1000) as u128,
+    })
+    .await
+}
@@ -1,5 +1,5 @@
 #![allow(clippy::large_enum_variant)]
-use std::time::Duration;
+use std::{path::Path, time::Duration};
 
 use actix_cors::Cors;
 use actix_files::{Files, NamedFile};
@@ -14,35 +14,119 @@ use passkey::create_webauthn;
 
 use crate::{
     authenticate::JwtAuthentication,
-    environment::{CORS_ORIGINS, HOST},
+    constants::{EMAIL_OUTBOX_INTERVAL, WEBHOOK_INTERVAL},
+    environment::{CORS_ORIGINS, EMAIL_ENABLED, HOST},
     utilities::{create_rate_limiter, create_success_rate_limiter},
 };
 
 pub mod authenticate;
+pub mod captcha;
 pub mod cleanup;
 pub mod constants;
 pub mod database;
+pub mod deletion;
+pub mod email;
+pub mod encryption;
 pub mod environment;
 pub mod errors;
+pub mod export;
+pub mod flows;
+pub mod geoip;
+pub mod machine;
+pub mod notifications;
+pub mod oidc;
 pub mod opaque;
 pub mod passkey;
+pub mod risk;
 pub mod routes;
+pub mod signing;
+pub mod templates;
 pub mod utilities;
+pub mod webhooks;
 
 #[async_std::main]
 async fn main() {
     dotenvy::dotenv().ok();
     env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
 
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("preview-emails") {
+        let out = args.next().unwrap_or("email-preview".to_string());
+        templates::preview(Path::new(&out)).expect("Failed to render email previews");
+        return;
+    }
+
     info!("Nextflow SSO system version {}", env!("CARGO_PKG_VERSION"));
     info!("Connecting to MongoDB...");
     database::connect().await;
+    templates::init();
+    email::init().await;
+    captcha::init();
+    database::audit_event::create_indexes()
+        .await
+        .expect("Failed to create audit event indexes");
+    database::email_outbox::create_indexes()
+        .await
+        .expect("Failed to create email outbox indexes");
+    database::event::create_indexes()
+        .await
+        .expect("Failed to create event indexes");
+    database::export::create_indexes()
+        .await
+        .expect("Failed to create export indexes");
+    database::failure_counter::create_indexes()
+        .await
+        .expect("Failed to create failure counter indexes");
+    database::webhook::create_indexes()
+        .await
+        .expect("Failed to create webhook indexes");
+    database::webhook_delivery::create_indexes()
+        .await
+        .expect("Failed to create webhook delivery indexes");
+    database::machine_client::create_indexes()
+        .await
+        .expect("Failed to create machine client indexes");
+    flows::init().await;
 
     info!("Spawning task to clean up expired entities...");
     task::spawn(async {
         loop {
             task::sleep(std::time::Duration::from_secs(60)).await;
-            task::spawn(async { cleanup::run() });
+            task::spawn(cleanup::run());
+        }
+    });
+
+    info!("Spawning task to rotate signing keys...");
+    task::spawn(async {
+        loop {
+            signing::rotate_keys().await;
+            task::sleep(std::time::Duration::from_secs(3600)).await;
+        }
+    });
+
+    info!("Spawning task to re-wrap encrypted secrets...");
+    task::spawn(async {
+        loop {
+            encryption::rewrap_secrets().await;
+            task::sleep(std::time::Duration::from_secs(3600)).await;
+        }
+    });
+
+    if *EMAIL_ENABLED {
+        info!("Spawning task to deliver queued emails...");
+        task::spawn(async {
+            loop {
+                email::deliver_queued().await;
+                task::sleep(Duration::from_secs(EMAIL_OUTBOX_INTERVAL)).await;
+            }
+        });
+    }
+
+    info!("Spawning task to deliver webhooks...");
+    task::spawn(async {
+        loop {
+            webhooks::deliver_queued().await;
+            task::sleep(Duration::from_secs(WEBHOOK_INTERVAL)).await;
         }
     });
 
@@ -69,6 +153,14 @@ async fn main() {
                     .supports_credentials(),
             )
             .wrap(Logger::default())
+            .route(
+                "/.well-known/openid-configuration",
+                web::get().to(routes::openid_configuration::handle),
+            )
+            .route(
+                "/.well-known/jwks.json",
+                web::get().to(routes::jwks::handle),
+            )
             .service(
                 web::scope("/api")
                     .app_data(create_webauthn())
@@ -81,10 +173,23 @@ async fn main() {
                             .to(routes::forgot::handle)
                             .wrap(create_success_rate_limiter(Duration::from_secs(21600), 10)),
                     )
+                    .route("/lockdown", web::post().to(routes::lockdown::handle))
+                    .route("/unlock", web::post().to(routes::unlock::handle))
                     .route("/user", web::patch().to(routes::account_settings::handle))
                     .route("/user", web::get().to(routes::current_user::handle))
                     .route("/user", web::delete().to(routes::delete::handle))
+                    .route("/user/restore", web::post().to(routes::restore::handle))
+                    .route("/user/export", web::post().to(routes::export::handle))
+                    .route(
+                        "/user/export/{id}",
+                        web::get().to(routes::get_export::handle),
+                    )
+                    .route(
+                        "/user/security-log",
+                        web::get().to(routes::security_log::handle),
+                    )
                     .route("/ip", web::get().to(routes::ip::handle))
+                    .route("/captcha", web::get().to(routes::captcha::handle))
                     .route("/session", web::get().to(routes::session::handle))
                     .route(
                         "/session",
@@ -92,6 +197,7 @@ async fn main() {
                             .to(routes::login::handle)
                             .wrap(create_success_rate_limiter(Duration::from_secs(20), 5)),
                     )
+                    .route("/session/refresh", web::post().to(routes::refresh::handle))
                     .route("/session", web::delete().to(routes::logout::handle))
                     .route(
                         "/session/{id}",
@@ -99,10 +205,24 @@ async fn main() {
                     )
                     .route("/session/all", web::delete().to(routes::logout_all::handle))
                     .route("/user/mfa", web::patch().to(routes::mfa::handle))
+                    .route("/user/mfa/codes", web::get().to(routes::get_codes::handle))
+                    .route(
+                        "/user/mfa/codes",
+                        web::post().to(routes::regenerate_codes::handle),
+                    )
                     .route(
                         "/user/profile",
                         web::patch().to(routes::profile_settings::handle),
                     )
+                    .route("/user/avatar", web::put().to(routes::update_avatar::handle))
+                    .route(
+                        "/user/avatar",
+                        web::delete().to(routes::delete_avatar::handle),
+                    )
+                    .route(
+                        "/avatar/default",
+                        web::get().to(routes::default_avatar::handle),
+                    )
                     .route(
                         "/user",
                         web::post()
@@ -118,6 +238,7 @@ async fn main() {
                         web::delete().to(routes::delete_passkey::handle),
                     )
                     .route("/user/passkeys", web::get().to(routes::get_passkey::handle))
+                    .route("/user/email", web::patch().to(routes::update_email::handle))
                     .route(
                         "/user/password",
                         web::patch().to(routes::update_password::handle),
@@ -127,6 +248,94 @@ async fn main() {
                         "/session/passkeys",
                         web::post().to(routes::login_passkey::handle),
                     )
+                    .route(
+                        "/oauth/authorize",
+                        web::post().to(routes::authorize::handle),
+                    )
+                    .route("/oauth/token", web::post().to(routes::token::handle))
+                    .route("/oauth/userinfo", web::get().to(routes::userinfo::handle))
+                    .route("/oauth/userinfo", web::post().to(routes::userinfo::handle))
+                    .route(
+                        "/service/users",
+                        web::post().to(routes::lookup_users::handle),
+                    )
+                    .route(
+                        "/service/tokens/verify",
+                        web::post().to(routes::verify_token::handle),
+                    )
+                    .route("/oauth/jwks", web::get().to(routes::jwks::handle))
+                    .route(
+                        "/oauth/clients",
+                        web::post().to(routes::create_client::handle),
+                    )
+                    .route(
+                        "/admin/users",
+                        web::get().to(routes::admin_search_users::handle),
+                    )
+                    .route(
+                        "/admin/users/{id}/status",
+                        web::put().to(routes::admin_status::handle),
+                    )
+                    .route(
+                        "/admin/users/{id}/sessions",
+                        web::get().to(routes::admin_sessions::handle),
+                    )
+                    .route(
+                        "/admin/users/{id}/sessions",
+                        web::delete().to(routes::admin_logout::handle),
+                    )
+                    .route(
+                        "/admin/users/{id}/passkeys",
+                        web::get().to(routes::admin_passkeys::handle),
+                    )
+                    .route(
+                        "/admin/users/{id}/mfa",
+                        web::delete().to(routes::admin_reset_mfa::handle),
+                    )
+                    .route(
+                        "/admin/users/{id}/password-reset",
+                        web::post().to(routes::admin_reset_password::handle),
+                    )
+                    .route(
+                        "/admin/email-outbox",
+                        web::get().to(routes::admin_email_outbox::handle),
+                    )
+                    .route(
+                        "/admin/webhooks",
+                        web::get().to(routes::admin_webhooks::handle),
+                    )
+                    .route(
+                        "/admin/webhooks",
+                        web::post().to(routes::admin_create_webhook::handle),
+                    )
+                    .route(
+                        "/admin/webhooks/{id}",
+                        web::delete().to(routes::admin_delete_webhook::handle),
+                    )
+                    .route(
+                        "/admin/webhooks/{id}/deliveries",
+                        web::get().to(routes::admin_webhook_deliveries::handle),
+                    )
+                    .route(
+                        "/admin/machine-clients",
+                        web::get().to(routes::admin_machine_clients::handle),
+                    )
+                    .route(
+                        "/admin/machine-clients",
+                        web::post().to(routes::admin_create_machine_client::handle),
+                    )
+                    .route(
+                        "/admin/machine-clients/{id}",
+                        web::delete().to(routes::admin_delete_machine_client::handle),
+                    )
+                    .route(
+                        "/admin/audit-events",
+                        web::get().to(routes::admin_audit_events::handle),
+                    )
+                    .route(
+                        "/metrics/flows",
+                        web::get().to(routes::flow_metrics::handle),
+                    )
                     .route(
                         "/validate",
                         web::post()
@@ -0,0 +1,157 @@
+use actix_web::HttpRequest;
+use async_std::task;
+use log::error;
+use mongodb::bson::{doc, DateTime};
+use serde_json::json;
+
+use crate::{
+    database::{
+        audit_event,
+        session::{self, get_ip, parse_user_agent, UserAgent},
+        user::User,
+    },
+    environment::PUBLIC_ROOT,
+    errors::Result,
+    geoip,
+    routes::lockdown::{PendingLockdown, PENDING_LOCKDOWNS},
+    templates::resolve_locale,
+    utilities::{generate_continue_token_long, get_time_millis, send_template_email},
+};
+
+pub enum SecurityNotice {
+    NewSignIn,
+    PasswordChanged,
+    MfaDisabled,
+    PasskeyAdded { name: String },
+    PasskeyRemoved { name: String },
+    UsernameChanged { username: String },
+    SessionsRevoked,
+}
+
+impl SecurityNotice {
+    // selects the wording in the security_notice template
+    fn kind(&self) -> &'static str {
+        match self {
+            SecurityNotice::NewSignIn => "new_sign_in",
+            SecurityNotice::PasswordChanged => "password_changed",
+            SecurityNotice::MfaDisabled => "mfa_disabled",
+            SecurityNotice::PasskeyAdded { .. } => "passkey_added",
+            SecurityNotice::PasskeyRemoved { .. } => "passkey_removed",
+            SecurityNotice::UsernameChanged { .. } => "username_changed",
+            SecurityNotice::SessionsRevoked => "sessions_revoked",
+        }
+    }
+}
+
+pub fn describe_device(user_agent: Option<UserAgent>) -> String {
+    let Some(user_agent) = user_agent else {
+        return "Unknown device".to_string();
+    };
+    // woothee reports fields it can't detect as UNKNOWN
+    let known = |s: &str| s != "UNKNOWN" && !s.is_empty();
+    let browser = if known(&user_agent.browser_version) {
+        format!("{} {}", user_agent.browser, user_agent.browser_version)
+    } else {
+        user_agent.browser
+    };
+    if known(&user_agent.os) {
+        format!("{} on {}", browser, user_agent.os)
+    } else {
+        browser
+    }
+}
+
+pub fn get_user_agent(req: &HttpRequest) -> Option<UserAgent> {
+    req.headers()
+        .get("User-Agent")
+        .and_then(|ua| ua.to_str().ok())
+        .and_then(parse_user_agent)
+}
+
+// a device is known if the user has a session on it or has signed in from it before
+pub async fn is_new_device(req: &HttpRequest, user_id: &str) -> Result<bool> {
+    let Some(user_agent) = get_user_agent(req) else {
+        return Ok(true);
+    };
+    let sessions = session::get_collection()
+        .count_documents(doc! {
+            "user_id": user_id,
+            "user_agent.browser": &user_agent.browser,
+            "user_agent.os": &user_agent.os
+        })
+        .await?;
+    if sessions > 0 {
+        return Ok(false);
+    }
+    let logins = audit_event::get_collection()
+        .count_documents(doc! {
+            "target_id": user_id,
+            "event": "login",
+            "user_agent.browser": &user_agent.browser,
+            "user_agent.os": &user_agent.os
+        })
+        .await?;
+    Ok(logins == 0)
+}
+
+// emails the user about a change to their account, with a link to lock it down
+// if the change wasn't theirs; sent once the change has been made, so a failure is
+// logged rather than failing a request whose change can't be undone
+pub async fn notify(req: &HttpRequest, user: &User, notice: SecurityNotice) {
+    let kind = notice.kind();
+    if let Err(e) = send_notice(req, user, notice).await {
+        error!(
+            "Failed to send {} notice to user {}: {:?}",
+            kind, user.id, e
+        );
+    }
+}
+
+// the time, IP address, location and device of the request, and a link to lock the
+// account down, included in every email about a change to an account
+pub async fn notice_context(req: &HttpRequest, user: &User) -> Result<serde_json::Value> {
+    let time = DateTime::from_millis(get_time_millis() as i64)
+        .try_to_rfc3339_string()
+        .unwrap_or_default();
+    let ip = get_ip(req);
+    let location = ip.as_deref().and_then(geoip::lookup);
+    let device = describe_device(get_user_agent(req));
+    let token = generate_continue_token_long();
+    PENDING_LOCKDOWNS
+        .insert(
+            &token,
+            &PendingLockdown {
+                user_id: user.id.clone(),
+            },
+        )
+        .await?;
+    let lockdown_url = format!("{}/lockdown?token={}", &*PUBLIC_ROOT, token);
+    Ok(json!({
+        "time": time,
+        "ip": ip.unwrap_or_else(|| "Unknown".to_string()),
+        "location": location,
+        "device": device,
+        "lockdown_url": lockdown_url,
+    }))
+}
+
+async fn send_notice(req: &HttpRequest, user: &User, notice: SecurityNotice) -> Result<()> {
+    let mut data = notice_context(req, user).await?;
+    data["notice"] = json!(notice.kind());
+    match notice {
+        SecurityNotice::PasskeyAdded { name } | SecurityNotice::PasskeyRemoved { name } => {
+            data["name"] = json!(name);
+        }
+        SecurityNotice::UsernameChanged { username } => {
+            data["username"] = json!(username);
+        }
+        _ => {}
+    }
+    task::spawn(send_template_email(
+        user.email.clone(),
+        "security_notice",
+        resolve_locale(user.locale.as_deref(), Some(req)),
+        data,
+    ));
+    Ok(())
+}
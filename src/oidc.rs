@@ -0,0 +1,108 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
+use jsonwebtoken::{Algorithm, Validation};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    database::{profile, user},
+    environment::PUBLIC_ROOT,
+    errors::{Error, Result},
+    signing,
+};
+
+pub const SUPPORTED_SCOPES: [&str; 3] = ["openid", "profile", "email"];
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub exp: u64,
+    pub iat: u64,
+    pub auth_time: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(flatten)]
+    pub user: UserClaims,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AccessTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub exp: u64,
+    pub iat: u64,
+    pub scope: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct UserClaims {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preferred_username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub website: Option<String>,
+}
+
+pub fn parse_scopes(scope: &str) -> Result<Vec<String>> {
+    let scopes = scope
+        .split_whitespace()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+    if !scopes.iter().any(|s| s == "openid") {
+        return Err(Error::InvalidScope);
+    }
+    if scopes
+        .iter()
+        .any(|s| !SUPPORTED_SCOPES.contains(&s.as_str()))
+    {
+        return Err(Error::InvalidScope);
+    }
+    Ok(scopes)
+}
+
+pub fn verify_pkce(challenge: &str, method: &str, verifier: &str) -> bool {
+    match method {
+        "S256" => BASE64.encode(Sha256::digest(verifier.as_bytes())) == challenge,
+        _ => false,
+    }
+}
+
+pub async fn get_user_claims(user_id: &str, scopes: &[String]) -> Result<UserClaims> {
+    let user = user::get_collection()
+        .find_one(doc! { "id": user_id })
+        .await?
+        .ok_or(Error::UserNotFound)?;
+    let mut claims = UserClaims::default();
+    if scopes.iter().any(|s| s == "email") {
+        claims.email = Some(user.email);
+        // addresses are verified during registration
+        claims.email_verified = Some(true);
+    }
+    if scopes.iter().any(|s| s == "profile") {
+        let profile = profile::get_collection()
+            .find_one(doc! { "id": user_id })
+            .await?
+            .ok_or(Error::UserNotFound)?;
+        claims.preferred_username = Some(user.username);
+        claims.name = Some(profile.display_name);
+        if !profile.website.is_empty() {
+            claims.website = Some(profile.website);
+        }
+    }
+    Ok(claims)
+}
+
+pub async fn validate_access_token(token: &str) -> Result<AccessTokenClaims> {
+    let mut validation = Validation::new(Algorithm::ES256);
+    validation.set_issuer(&[&*PUBLIC_ROOT]);
+    validation.validate_aud = false;
+    signing::decode::<AccessTokenClaims>(token, validation).await
+}
@@ -0,0 +1,220 @@
+use actix_web::HttpRequest;
+use async_std::task;
+use mongodb::bson::{doc, DateTime};
+use serde_json::json;
+
+use crate::{
+    captcha::validate_captcha,
+    constants::{
+        ACCOUNT_BACKOFF_BASE, ACCOUNT_BACKOFF_MAX, ACCOUNT_BACKOFF_THRESHOLD,
+        ACCOUNT_FAILURE_WINDOW, ACCOUNT_LOCKOUT_DURATION, ACCOUNT_LOCKOUT_THRESHOLD,
+        CONTINUE_TIMEOUT, FAILURE_WINDOW, LOGIN_CAPTCHA_EMAIL_THRESHOLD,
+        LOGIN_CAPTCHA_IP_THRESHOLD, MFA_TOKEN_MAX_ATTEMPTS,
+    },
+    database::{
+        audit_event::{self, AuditEventKind},
+        failure_counter::{self, FailureScope},
+        session::{self, get_ip},
+        user::User,
+    },
+    environment::PUBLIC_ROOT,
+    errors::{Error, Result},
+    geoip,
+    notifications::{describe_device, get_user_agent},
+    routes::{
+        login::PENDING_MFAS,
+        unlock::{PendingUnlock, PENDING_UNLOCKS},
+    },
+    templates::resolve_locale,
+    utilities::{generate_continue_token_long, get_time_secs, send_template_email},
+};
+
+// Login and password reset only ask for a CAPTCHA once a request looks risky, so
+// ordinary users aren't challenged but credential stuffing and email bombing are
+// slowed down. A request that needs one fails with CAPTCHA_REQUIRED until it is
+// repeated with a captchaToken.
+//
+// Failed passwords and MFA codes are also counted per account, whatever the IP
+// address. After a few, each attempt must wait twice as long as the last; after
+// more, password sign-ins are locked for a while and the user is emailed a link
+// to unlock them.
+
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+pub async fn check_captcha(
+    req: &HttpRequest,
+    captcha_token: Option<String>,
+    required: bool,
+) -> Result<()> {
+    if !required {
+        return Ok(());
+    }
+    let Some(captcha_token) = captcha_token else {
+        return Err(Error::CaptchaRequired);
+    };
+    validate_captcha(req, &captcha_token).await
+}
+
+// after repeated incorrect passwords for the email or from the IP address
+pub async fn login_requires_captcha(req: &HttpRequest, email: &str) -> Result<bool> {
+    let email_failures =
+        failure_counter::get_count(FailureScope::LoginEmail, &normalize_email(email)).await?;
+    if email_failures >= LOGIN_CAPTCHA_EMAIL_THRESHOLD {
+        return Ok(true);
+    }
+    let Some(ip) = get_ip(req) else {
+        return Ok(false);
+    };
+    let ip_failures = failure_counter::get_count(FailureScope::LoginIp, &ip).await?;
+    Ok(ip_failures >= LOGIN_CAPTCHA_IP_THRESHOLD)
+}
+
+pub async fn record_login_failure(req: &HttpRequest, email: &str) -> Result<()> {
+    failure_counter::increment(
+        FailureScope::LoginEmail,
+        &normalize_email(email),
+        FAILURE_WINDOW,
+    )
+    .await?;
+    if let Some(ip) = get_ip(req) {
+        failure_counter::increment(FailureScope::LoginIp, &ip, FAILURE_WINDOW).await?;
+    }
+    Ok(())
+}
+
+// the IP address's count is kept, as it may be trying many accounts
+pub async fn clear_login_failures(email: &str) -> Result<()> {
+    failure_counter::clear(FailureScope::LoginEmail, &normalize_email(email)).await
+}
+
+// unless the account has signed in from the IP address before; requests for
+// unknown emails are treated the same as a new IP address
+pub async fn reset_requires_captcha(req: &HttpRequest, user: Option<&User>) -> Result<bool> {
+    let (Some(user), Some(ip)) = (user, get_ip(req)) else {
+        return Ok(true);
+    };
+    let session = session::get_collection()
+        .find_one(doc! {
+            "user_id": &user.id,
+            "ip": &ip
+        })
+        .await?;
+    if session.is_some() {
+        return Ok(false);
+    }
+    let login = audit_event::get_collection()
+        .find_one(doc! {
+            "target_id": &user.id,
+            "event": "login",
+            "ip": &ip
+        })
+        .await?;
+    Ok(login.is_none())
+}
+
+fn get_backoff(failures: u64) -> u64 {
+    let doublings = failures.saturating_sub(ACCOUNT_BACKOFF_THRESHOLD).min(16);
+    ACCOUNT_BACKOFF_BASE
+        .saturating_mul(1 << doublings)
+        .min(ACCOUNT_BACKOFF_MAX)
+}
+
+// refuses password and MFA attempts while the account is locked or backing off
+pub async fn check_account_throttle(user_id: &str) -> Result<()> {
+    let Some(counter) = failure_counter::get(FailureScope::Account, user_id).await? else {
+        return Ok(());
+    };
+    let now = get_time_secs();
+    if let Some(until) = counter.locked_until {
+        if until > now {
+            return Err(Error::AccountTemporarilyLocked { until });
+        }
+    }
+    if counter.count >= ACCOUNT_BACKOFF_THRESHOLD {
+        let retry_at = counter.last_failed_at + get_backoff(counter.count);
+        if retry_at > now {
+            return Err(Error::TooManyAttempts {
+                retry_after: retry_at - now,
+            });
+        }
+    }
+    Ok(())
+}
+
+pub async fn record_account_failure(req: &HttpRequest, user: &User) -> Result<()> {
+    let counter =
+        failure_counter::increment(FailureScope::Account, &user.id, ACCOUNT_FAILURE_WINDOW).await?;
+    if counter.count < ACCOUNT_LOCKOUT_THRESHOLD {
+        return Ok(());
+    }
+    let until = get_time_secs() + ACCOUNT_LOCKOUT_DURATION;
+    if !failure_counter::lock(FailureScope::Account, &user.id, until).await? {
+        return Ok(());
+    }
+    audit_event::record(
+        req,
+        AuditEventKind::SignInLocked,
+        None,
+        &user.id,
+        None,
+        Some(format!("{} failed attempts", counter.count)),
+    )
+    .await;
+    send_lockout_email(req, user, until).await
+}
+
+// a continue token only allows a few codes before the login has to start over
+pub async fn record_mfa_failure(
+    req: &HttpRequest,
+    user: &User,
+    continue_token: &str,
+) -> Result<()> {
+    record_account_failure(req, user).await?;
+    let counter =
+        failure_counter::increment(FailureScope::MfaToken, continue_token, CONTINUE_TIMEOUT)
+            .await?;
+    if counter.count >= MFA_TOKEN_MAX_ATTEMPTS {
+        PENDING_MFAS.complete(continue_token).await?;
+    }
+    Ok(())
+}
+
+pub async fn clear_account_failures(user_id: &str) -> Result<()> {
+    failure_counter::clear(FailureScope::Account, user_id).await
+}
+
+async fn send_lockout_email(req: &HttpRequest, user: &User, until: u64) -> Result<()> {
+    let format_time = |secs: u64| {
+        DateTime::from_millis((secs * 1000) as i64)
+            .try_to_rfc3339_string()
+            .unwrap_or_default()
+    };
+    let ip = get_ip(req);
+    let location = ip.as_deref().and_then(geoip::lookup);
+    let token = generate_continue_token_long();
+    PENDING_UNLOCKS
+        .insert(
+            &token,
+            &PendingUnlock {
+                user_id: user.id.clone(),
+            },
+        )
+        .await?;
+    let data = json!({
+        "time": format_time(get_time_secs()),
+        "until": format_time(until),
+        "ip": ip.unwrap_or_else(|| "Unknown".to_string()),
+        "location": location,
+        "device": describe_device(get_user_agent(req)),
+        "unlock_url": format!("{}/unlock?token={}", &*PUBLIC_ROOT, token),
+    });
+    task::spawn(send_template_email(
+        user.email.clone(),
+        "sign_in_locked",
+        resolve_locale(user.locale.as_deref(), Some(req)),
+        data,
+    ));
+    Ok(())
+}
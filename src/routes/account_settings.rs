@@ -1,18 +1,24 @@
-use actix_web::{web, Responder};
+use actix_web::{web, HttpRequest, Responder};
 use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
 use crate::{
     authenticate::Authenticate,
-    database::user::get_collection,
+    database::{
+        event::{self, EventKind},
+        user::get_collection,
+    },
     errors::{Error, Result},
-    utilities::{validate_escalation, USERNAME_RE},
+    notifications::{notify, SecurityNotice},
+    utilities::{validate_escalation, LOCALE_RE, USERNAME_RE},
 };
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AccountSettings {
     username: Option<String>,
+    locale: Option<String>,
     // destructive actions
     escalation_token: String,
 }
@@ -22,15 +28,16 @@ pub struct AccountSettings {
 pub struct AccountSettingsResponse {}
 
 pub async fn handle(
+    req: HttpRequest,
     jwt: web::ReqData<Result<Authenticate>>,
     account_settings: web::Json<AccountSettings>,
 ) -> Result<impl Responder> {
     let jwt = jwt.into_inner()?;
     let account_settings = account_settings.into_inner();
-    validate_escalation(account_settings.escalation_token, jwt.jwt).await?;
+    validate_escalation(account_settings.escalation_token, jwt.session_id).await?;
     let user_collection = get_collection();
     let mut update_query = doc! {};
-    if let Some(username) = account_settings.username {
+    if let Some(username) = &account_settings.username {
         if !USERNAME_RE.is_match(username.trim()) {
             return Err(Error::InvalidUsername);
         }
@@ -44,8 +51,14 @@ pub async fn handle(
         }
         update_query.insert("username", username.trim());
     }
-    user_collection
-        .update_one(
+    if let Some(locale) = &account_settings.locale {
+        if !LOCALE_RE.is_match(locale) {
+            return Err(Error::InvalidLocale);
+        }
+        update_query.insert("locale", locale);
+    }
+    let user = user_collection
+        .find_one_and_update(
             doc! {
                 "id": jwt.jwt_content.id.clone()
             },
@@ -53,6 +66,23 @@ pub async fn handle(
                 "$set": update_query
             },
         )
-        .await?;
+        .await?
+        .ok_or(Error::DatabaseError)?;
+    if let Some(username) = account_settings.username {
+        event::publish(
+            EventKind::UserUpdated,
+            &user.id,
+            Some(json!({ "fields": ["username"] })),
+        )
+        .await;
+        notify(
+            &req,
+            &user,
+            SecurityNotice::UsernameChanged {
+                username: username.trim().to_string(),
+            },
+        )
+        .await;
+    }
     Ok(web::Json(AccountSettingsResponse {}))
 }
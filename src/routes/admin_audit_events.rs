@@ -0,0 +1,82 @@
+use actix_web::{web, HttpRequest, Responder};
+use mongodb::bson::{self, doc};
+use serde::{Deserialize, Serialize};
+
+use super::security_log::{page_size, AuditEventEntry, AuditEventPage};
+use crate::{
+    authenticate::Authenticate,
+    database::{
+        admin_action::{self, AdminActionKind},
+        audit_event::{self, AuditEventKind},
+    },
+    errors::{Error, Result},
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminAuditEvents {
+    target_id: Option<String>,
+    actor_id: Option<String>,
+    session_id: Option<String>,
+    ip: Option<String>,
+    event: Option<AuditEventKind>,
+    // seconds, inclusive
+    since: Option<u64>,
+    until: Option<u64>,
+    before: Option<String>,
+    limit: Option<i64>,
+}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    query: web::Query<AdminAuditEvents>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    let query = query.into_inner();
+    let mut filter = doc! {};
+    if let Some(target_id) = &query.target_id {
+        filter.insert("target_id", target_id.as_str());
+    }
+    if let Some(actor_id) = &query.actor_id {
+        filter.insert("actor_id", actor_id.as_str());
+    }
+    if let Some(session_id) = &query.session_id {
+        filter.insert("session_id", session_id.as_str());
+    }
+    if let Some(ip) = &query.ip {
+        filter.insert("ip", ip.as_str());
+    }
+    if let Some(event) = &query.event {
+        filter.insert(
+            "event",
+            bson::to_bson(event).map_err(|_| Error::DatabaseError)?,
+        );
+    }
+    let mut created_at = doc! {};
+    if let Some(since) = query.since {
+        created_at.insert("$gte", since as i64);
+    }
+    if let Some(until) = query.until {
+        created_at.insert("$lte", until as i64);
+    }
+    if !created_at.is_empty() {
+        filter.insert("created_at", created_at);
+    }
+    let detail = filter.to_string();
+    let (events, next_cursor) =
+        audit_event::query(filter, query.before, page_size(query.limit)).await?;
+    admin_action::record(
+        &admin.id,
+        AdminActionKind::ViewAuditEvents,
+        query.target_id.as_deref(),
+        Some(detail),
+    )
+    .await;
+    Ok(web::Json(AuditEventPage {
+        events: events.into_iter().map(AuditEventEntry::from).collect(),
+        next_cursor,
+    }))
+}
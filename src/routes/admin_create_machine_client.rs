@@ -0,0 +1,77 @@
+use actix_web::{web, HttpRequest, Responder};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::{
+    authenticate::Authenticate,
+    database::{
+        admin_action::{self, AdminActionKind},
+        machine_client::{self, MachineClient, MachineScope},
+    },
+    errors::Result,
+    machine::validate_public_key,
+    utilities::{generate_continue_token_long, get_time_secs, hash_secret, validate_administrator},
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminCreateMachineClient {
+    name: String,
+    scopes: Vec<MachineScope>,
+    // PEM; the client then signs JWT assertions instead of being given a secret
+    public_key: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminCreateMachineClientResponse {
+    id: String,
+    // only shown once, and only for clients without a public key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    create_client: web::Json<AdminCreateMachineClient>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    let create_client = create_client.into_inner();
+    let public_key = create_client
+        .public_key
+        .map(|public_key| public_key.trim().to_string())
+        .filter(|public_key| !public_key.is_empty());
+    if let Some(public_key) = &public_key {
+        validate_public_key(public_key)?;
+    }
+    let secret = match public_key {
+        Some(_) => None,
+        None => Some(generate_continue_token_long()),
+    };
+    let scopes = MachineScope::ALL
+        .into_iter()
+        .filter(|scope| create_client.scopes.contains(scope))
+        .collect();
+    let id = Ulid::new().to_string();
+    machine_client::get_collection()
+        .insert_one(MachineClient {
+            id: id.clone(),
+            name: create_client.name.trim().to_string(),
+            secret: secret.as_deref().map(hash_secret),
+            public_key,
+            scopes,
+            created_by: admin.id.clone(),
+            created_at: get_time_secs(),
+        })
+        .await?;
+    admin_action::record(
+        &admin.id,
+        AdminActionKind::CreateMachineClient,
+        None,
+        Some(id.clone()),
+    )
+    .await;
+    Ok(web::Json(AdminCreateMachineClientResponse { id, secret }))
+}
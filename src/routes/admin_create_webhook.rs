@@ -0,0 +1,68 @@
+use actix_web::{web, HttpRequest, Responder};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::{
+    authenticate::Authenticate,
+    database::{
+        admin_action::{self, AdminActionKind},
+        event::EventKind,
+        webhook::{self, Webhook},
+    },
+    encryption,
+    errors::{Error, Result},
+    utilities::{generate_continue_token_long, get_time_secs, validate_administrator},
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminCreateWebhook {
+    url: String,
+    #[serde(default)]
+    description: String,
+    events: Vec<EventKind>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminCreateWebhookResponse {
+    id: String,
+    // only shown once; verify X-Webhook-Signature with it
+    secret: String,
+}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    create_webhook: web::Json<AdminCreateWebhook>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    let create_webhook = create_webhook.into_inner();
+    let url = Url::parse(create_webhook.url.trim()).map_err(|_| Error::InvalidWebhookUrl)?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(Error::InvalidWebhookUrl);
+    }
+    let secret = generate_continue_token_long();
+    let id = Ulid::new().to_string();
+    webhook::get_collection()
+        .insert_one(Webhook {
+            id: id.clone(),
+            url: url.to_string(),
+            description: create_webhook.description.trim().to_string(),
+            events: create_webhook.events,
+            secret: encryption::seal(&secret).await?,
+            created_by: admin.id.clone(),
+            created_at: get_time_secs(),
+        })
+        .await?;
+    admin_action::record(
+        &admin.id,
+        AdminActionKind::CreateWebhook,
+        None,
+        Some(format!("{} {}", id, url)),
+    )
+    .await;
+    Ok(web::Json(AdminCreateWebhookResponse { id, secret }))
+}
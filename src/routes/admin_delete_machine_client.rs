@@ -0,0 +1,41 @@
+use actix_web::{web, HttpRequest, Responder};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::{
+        admin_action::{self, AdminActionKind},
+        machine_client,
+    },
+    errors::{Error, Result},
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminDeleteMachineClientResponse {}
+
+// the client's access tokens stop working straight away
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    client_id: web::Path<String>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    let client = machine_client::get_collection()
+        .find_one_and_delete(doc! {
+            "id": client_id.into_inner()
+        })
+        .await?
+        .ok_or(Error::MachineClientNotFound)?;
+    admin_action::record(
+        &admin.id,
+        AdminActionKind::DeleteMachineClient,
+        None,
+        Some(format!("{} {}", client.id, client.name)),
+    )
+    .await;
+    Ok(web::Json(AdminDeleteMachineClientResponse {}))
+}
@@ -0,0 +1,47 @@
+use actix_web::{web, HttpRequest, Responder};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::{
+        admin_action::{self, AdminActionKind},
+        webhook, webhook_delivery,
+    },
+    errors::{Error, Result},
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminDeleteWebhookResponse {}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    webhook_id: web::Path<String>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    let webhook_id = webhook_id.into_inner();
+    let webhook = webhook::get_collection()
+        .find_one_and_delete(doc! {
+            "id": &webhook_id
+        })
+        .await?
+        .ok_or(Error::WebhookNotFound)?;
+    // the delivery log goes with it
+    webhook_delivery::get_collection()
+        .delete_many(doc! {
+            "webhook_id": &webhook_id
+        })
+        .await?;
+    admin_action::record(
+        &admin.id,
+        AdminActionKind::DeleteWebhook,
+        None,
+        Some(format!("{} {}", webhook.id, webhook.url)),
+    )
+    .await;
+    Ok(web::Json(AdminDeleteWebhookResponse {}))
+}
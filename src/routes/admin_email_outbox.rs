@@ -0,0 +1,92 @@
+use actix_web::{web, HttpRequest, Responder};
+use futures_util::StreamExt;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    constants::EMAIL_OUTBOX_FAILURES,
+    database::{
+        admin_action::{self, AdminActionKind},
+        email_outbox::{self, OutboxEmail},
+    },
+    errors::Result,
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FailedEmailEntry {
+    id: String,
+    to: String,
+    template: String,
+    attempts: u32,
+    last_error: Option<String>,
+    created_at: u64,
+    failed_at: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailOutboxResponse {
+    pending: u64,
+    // pending emails that have failed at least once and are waiting to retry
+    retrying: u64,
+    sending: u64,
+    sent: u64,
+    failed: u64,
+    recent_failures: Vec<FailedEmailEntry>,
+}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    let collection = email_outbox::get_collection();
+    let pending = collection
+        .count_documents(doc! { "status": "pending" })
+        .await?;
+    let retrying = collection
+        .count_documents(doc! { "status": "pending", "attempts": { "$gt": 0 } })
+        .await?;
+    let sending = collection
+        .count_documents(doc! { "status": "sending" })
+        .await?;
+    let sent = collection
+        .count_documents(doc! { "status": "sent" })
+        .await?;
+    let failed = collection
+        .count_documents(doc! { "status": "failed" })
+        .await?;
+    let recent_failures = collection
+        .find(doc! { "status": "failed" })
+        .sort(doc! { "updated_at": -1 })
+        .limit(EMAIL_OUTBOX_FAILURES)
+        .await?
+        .collect::<Vec<std::result::Result<OutboxEmail, _>>>()
+        .await
+        .into_iter()
+        .collect::<std::result::Result<Vec<OutboxEmail>, _>>()?
+        .into_iter()
+        .map(|email| FailedEmailEntry {
+            id: email.id,
+            to: email.to,
+            template: email.template,
+            attempts: email.attempts,
+            last_error: email.last_error,
+            created_at: email.created_at,
+            failed_at: email.updated_at,
+        })
+        .collect::<Vec<_>>();
+    admin_action::record(&admin.id, AdminActionKind::ViewEmailOutbox, None, None).await;
+    Ok(web::Json(EmailOutboxResponse {
+        pending,
+        retrying,
+        sending,
+        sent,
+        failed,
+        recent_failures,
+    }))
+}
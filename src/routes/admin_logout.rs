@@ -0,0 +1,60 @@
+use actix_web::{web, HttpRequest, Responder};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::{
+        admin_action::{self, AdminActionKind},
+        audit_event::{self, AuditEventKind},
+        session, user,
+    },
+    errors::{Error, Result},
+    notifications::{notify, SecurityNotice},
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminLogoutResponse {
+    revoked: u64,
+}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    user_id: web::Path<String>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    let user_id = user_id.into_inner();
+    // access tokens already issued stay valid until they expire, but can't be refreshed
+    let user = user::get_collection()
+        .find_one(doc! {
+            "id": &user_id
+        })
+        .await?
+        .ok_or(Error::UserNotFound)?;
+    let revoked = session::revoke(doc! {
+        "user_id": &user_id
+    })
+    .await?;
+    audit_event::record(
+        &req,
+        AuditEventKind::SessionsRevoked,
+        Some(&admin.id),
+        &user_id,
+        None,
+        None,
+    )
+    .await;
+    notify(&req, &user, SecurityNotice::SessionsRevoked).await;
+    admin_action::record(
+        &admin.id,
+        AdminActionKind::ForceLogout,
+        Some(&user_id),
+        Some(format!("{} sessions revoked", revoked)),
+    )
+    .await;
+    Ok(web::Json(AdminLogoutResponse { revoked }))
+}
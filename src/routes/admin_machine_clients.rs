@@ -0,0 +1,62 @@
+use actix_web::{web, HttpRequest, Responder};
+use futures_util::TryStreamExt;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::{
+        admin_action::{self, AdminActionKind},
+        machine_client::{self, MachineClient, MachineScope},
+    },
+    errors::Result,
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MachineClientEntry {
+    id: String,
+    name: String,
+    scopes: Vec<MachineScope>,
+    // set for clients that sign assertions rather than sending a secret
+    public_key: Option<String>,
+    created_by: String,
+    created_at: u64,
+}
+
+impl From<MachineClient> for MachineClientEntry {
+    fn from(client: MachineClient) -> Self {
+        MachineClientEntry {
+            id: client.id,
+            name: client.name,
+            scopes: client.scopes,
+            public_key: client.public_key,
+            created_by: client.created_by,
+            created_at: client.created_at,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminMachineClientsResponse {
+    clients: Vec<MachineClientEntry>,
+}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    let clients = machine_client::get_collection()
+        .find(doc! {})
+        .sort(doc! { "id": 1 })
+        .await?
+        .map_ok(MachineClientEntry::from)
+        .try_collect()
+        .await?;
+    admin_action::record(&admin.id, AdminActionKind::ViewMachineClients, None, None).await;
+    Ok(web::Json(AdminMachineClientsResponse { clients }))
+}
@@ -0,0 +1,57 @@
+use actix_web::{web, HttpRequest, Responder};
+use futures_util::StreamExt;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::{
+        admin_action::{self, AdminActionKind},
+        passkey,
+    },
+    errors::Result,
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminPasskeyEntry {
+    id: String,
+    friendly_name: String,
+    credential_id: String,
+}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    user_id: web::Path<String>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    let user_id = user_id.into_inner();
+    let passkeys = passkey::get_collection()
+        .find(doc! {
+            "user_id": &user_id
+        })
+        .await?
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<std::result::Result<Vec<_>, mongodb::error::Error>>()?;
+    admin_action::record(
+        &admin.id,
+        AdminActionKind::ViewPasskeys,
+        Some(&user_id),
+        None,
+    )
+    .await;
+    let passkeys = passkeys
+        .into_iter()
+        .map(|p| AdminPasskeyEntry {
+            id: p.id,
+            friendly_name: p.friendly_name,
+            credential_id: p.credential_id,
+        })
+        .collect::<Vec<_>>();
+    Ok(web::Json(passkeys))
+}
@@ -0,0 +1,71 @@
+use actix_web::{web, HttpRequest, Responder};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    authenticate::Authenticate,
+    database::{
+        admin_action::{self, AdminActionKind},
+        audit_event::{self, AuditEventKind},
+        code,
+        event::{self, EventKind},
+        user,
+    },
+    errors::{Error, Result},
+    notifications::{notify, SecurityNotice},
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminResetMfaResponse {}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    user_id: web::Path<String>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    let user_id = user_id.into_inner();
+    let user = user::get_collection()
+        .find_one_and_update(
+            doc! {
+                "id": &user_id
+            },
+            doc! {
+                "$set": {
+                    "mfa_enabled": false,
+                    "mfa_secret": None::<String>
+                }
+            },
+        )
+        .await?
+        .ok_or(Error::UserNotFound)?;
+    code::get_collection()
+        .delete_many(doc! {
+            "user_id": &user_id
+        })
+        .await?;
+    audit_event::record(
+        &req,
+        AuditEventKind::MfaDisabled,
+        Some(&admin.id),
+        &user_id,
+        None,
+        None,
+    )
+    .await;
+    if user.mfa_enabled {
+        event::publish(
+            EventKind::MfaChanged,
+            &user_id,
+            Some(json!({ "enabled": false })),
+        )
+        .await;
+    }
+    notify(&req, &user, SecurityNotice::MfaDisabled).await;
+    admin_action::record(&admin.id, AdminActionKind::ResetMfa, Some(&user_id), None).await;
+    Ok(web::Json(AdminResetMfaResponse {}))
+}
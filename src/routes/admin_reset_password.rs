@@ -0,0 +1,65 @@
+use actix_web::{web, HttpRequest, Responder};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::{
+        admin_action::{self, AdminActionKind},
+        user,
+    },
+    environment::EMAIL_ENABLED,
+    errors::{Error, Result},
+    templates::resolve_locale,
+    utilities::{generate_continue_token_long, send_reset_email, validate_administrator},
+};
+
+use super::forgot::{PendingForgot, PENDING_FORGOTS1};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminResetPasswordResponse {}
+
+// starts the same flow as /api/forgot, so the user still chooses their own password
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    user_id: web::Path<String>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    if !*EMAIL_ENABLED {
+        return Err(Error::EmailMisconfigured);
+    }
+    let user = user::get_collection()
+        .find_one(doc! {
+            "id": user_id.into_inner()
+        })
+        .await?
+        .ok_or(Error::UserNotFound)?;
+    let token = generate_continue_token_long();
+    PENDING_FORGOTS1
+        .insert(
+            &token,
+            &PendingForgot {
+                user_id: user.id.clone(),
+                email: user.email.clone(),
+            },
+        )
+        .await?;
+    // the request's Accept-Language is the administrator's, not the user's
+    send_reset_email(
+        user.email,
+        resolve_locale(user.locale.as_deref(), None),
+        token,
+    )
+    .await?;
+    admin_action::record(
+        &admin.id,
+        AdminActionKind::SendPasswordReset,
+        Some(&user.id),
+        None,
+    )
+    .await;
+    Ok(web::Json(AdminResetPasswordResponse {}))
+}
@@ -0,0 +1,80 @@
+use actix_web::{web, HttpRequest, Responder};
+use futures_util::StreamExt;
+use mongodb::bson::{doc, Regex};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    constants::ADMIN_SEARCH_LIMIT,
+    database::{
+        admin_action::{self, AdminActionKind},
+        user::{self, AccountStatus, User},
+    },
+    errors::Result,
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchUsers {
+    query: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminUserEntry {
+    id: String,
+    email: String,
+    username: String,
+    mfa_enabled: bool,
+    platform_administrator: bool,
+    status: AccountStatus,
+}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    search: web::Query<SearchUsers>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    let query = search.into_inner().query.trim().to_string();
+    // IDs match exactly, emails case-insensitively and usernames by prefix
+    let escaped = regex::escape(&query);
+    let email = Regex {
+        pattern: format!("^{}$", escaped),
+        options: "i".to_string(),
+    };
+    let username = Regex {
+        pattern: format!("^{}", escaped),
+        options: "i".to_string(),
+    };
+    let users = user::get_collection()
+        .find(doc! {
+            "$or": [
+                { "id": &query },
+                { "email": email },
+                { "username": username },
+            ]
+        })
+        .sort(doc! { "username": 1 })
+        .limit(ADMIN_SEARCH_LIMIT)
+        .await?
+        .collect::<Vec<std::result::Result<User, _>>>()
+        .await
+        .into_iter()
+        .collect::<std::result::Result<Vec<User>, _>>()?;
+    admin_action::record(&admin.id, AdminActionKind::SearchUsers, None, Some(query)).await;
+    let users = users
+        .into_iter()
+        .map(|user| AdminUserEntry {
+            id: user.id,
+            email: user.email,
+            username: user.username,
+            mfa_enabled: user.mfa_enabled,
+            platform_administrator: user.platform_administrator,
+            status: user.status,
+        })
+        .collect::<Vec<_>>();
+    Ok(web::Json(users))
+}
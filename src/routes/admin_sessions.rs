@@ -0,0 +1,67 @@
+use actix_web::{web, HttpRequest, Responder};
+use futures_util::StreamExt;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::{
+        admin_action::{self, AdminActionKind},
+        session::{self, Session, UserAgent},
+    },
+    errors::Result,
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminSessionEntry {
+    id: String,
+    friendly_name: String,
+    created_at: u64,
+    last_used_at: u64,
+    expires_at: u64,
+    ip: Option<String>,
+    user_agent: Option<UserAgent>,
+    location: Option<String>,
+}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    user_id: web::Path<String>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    let user_id = user_id.into_inner();
+    let sessions = session::get_collection()
+        .find(doc! {
+            "user_id": &user_id
+        })
+        .await?
+        .collect::<Vec<std::result::Result<Session, _>>>()
+        .await
+        .into_iter()
+        .collect::<std::result::Result<Vec<Session>, _>>()?;
+    admin_action::record(
+        &admin.id,
+        AdminActionKind::ViewSessions,
+        Some(&user_id),
+        None,
+    )
+    .await;
+    let sessions = sessions
+        .into_iter()
+        .map(|session| AdminSessionEntry {
+            id: session.id,
+            friendly_name: session.friendly_name,
+            created_at: session.created_at,
+            last_used_at: session.last_used_at,
+            expires_at: session.expires_at,
+            ip: session.ip,
+            user_agent: session.user_agent,
+            location: session.location,
+        })
+        .collect::<Vec<_>>();
+    Ok(web::Json(sessions))
+}
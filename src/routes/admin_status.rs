@@ -0,0 +1,74 @@
+use actix_web::{web, HttpRequest, Responder};
+use mongodb::bson::{self, doc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::{
+        admin_action::{self, AdminActionKind},
+        audit_event::{self, AuditEventKind},
+        session,
+        user::{self, AccountStatus},
+    },
+    errors::{Error, Result},
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminStatusResponse {}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    user_id: web::Path<String>,
+    status: web::Json<AccountStatus>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    let user_id = user_id.into_inner();
+    let status = status.into_inner();
+    let active = matches!(status, AccountStatus::Active);
+    if !active && user_id == admin.id {
+        // an administrator locking themselves out is almost certainly a mistake
+        return Err(Error::MissingPermission);
+    }
+    let result = user::get_collection()
+        .update_one(
+            doc! {
+                "id": &user_id
+            },
+            doc! {
+                "$set": {
+                    "status": bson::to_bson(&status).map_err(|_| Error::DatabaseError)?
+                }
+            },
+        )
+        .await?;
+    if result.matched_count == 0 {
+        return Err(Error::UserNotFound);
+    }
+    if !active {
+        session::revoke(doc! {
+            "user_id": &user_id
+        })
+        .await?;
+    }
+    audit_event::record(
+        &req,
+        AuditEventKind::StatusChanged,
+        Some(&admin.id),
+        &user_id,
+        None,
+        serde_json::to_string(&status).ok(),
+    )
+    .await;
+    admin_action::record(
+        &admin.id,
+        AdminActionKind::UpdateStatus,
+        Some(&user_id),
+        serde_json::to_string(&status).ok(),
+    )
+    .await;
+    Ok(web::Json(AdminStatusResponse {}))
+}
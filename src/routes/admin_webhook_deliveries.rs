@@ -0,0 +1,103 @@
+use actix_web::{web, HttpRequest, Responder};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    constants::WEBHOOK_DELIVERY_PAGE_SIZE,
+    database::{
+        admin_action::{self, AdminActionKind},
+        email_outbox::DeliveryStatus,
+        event::EventKind,
+        webhook,
+        webhook_delivery::{self, WebhookDelivery},
+    },
+    errors::{Error, Result},
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminWebhookDeliveries {
+    before: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDeliveryEntry {
+    id: String,
+    event_id: String,
+    event: EventKind,
+    status: DeliveryStatus,
+    attempts: u32,
+    // when a pending delivery will be retried
+    next_attempt_at: u64,
+    response_status: Option<u16>,
+    last_error: Option<String>,
+    created_at: u64,
+    updated_at: u64,
+    delivered_at: Option<u64>,
+}
+
+impl From<WebhookDelivery> for WebhookDeliveryEntry {
+    fn from(delivery: WebhookDelivery) -> Self {
+        WebhookDeliveryEntry {
+            id: delivery.id,
+            event_id: delivery.event_id,
+            event: delivery.event,
+            status: delivery.status,
+            attempts: delivery.attempts,
+            next_attempt_at: delivery.next_attempt_at,
+            response_status: delivery.response_status,
+            last_error: delivery.last_error,
+            created_at: delivery.created_at,
+            updated_at: delivery.updated_at,
+            delivered_at: delivery.delivered_at,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDeliveryPage {
+    deliveries: Vec<WebhookDeliveryEntry>,
+    // pass as `before` to fetch the next (older) page
+    next_cursor: Option<String>,
+}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    webhook_id: web::Path<String>,
+    query: web::Query<AdminWebhookDeliveries>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    let webhook_id = webhook_id.into_inner();
+    webhook::get_collection()
+        .find_one(doc! {
+            "id": &webhook_id
+        })
+        .await?
+        .ok_or(Error::WebhookNotFound)?;
+    let (deliveries, next_cursor) = webhook_delivery::query(
+        &webhook_id,
+        query.into_inner().before,
+        WEBHOOK_DELIVERY_PAGE_SIZE,
+    )
+    .await?;
+    admin_action::record(
+        &admin.id,
+        AdminActionKind::ViewWebhookDeliveries,
+        None,
+        Some(webhook_id),
+    )
+    .await;
+    Ok(web::Json(WebhookDeliveryPage {
+        deliveries: deliveries
+            .into_iter()
+            .map(WebhookDeliveryEntry::from)
+            .collect(),
+        next_cursor,
+    }))
+}
@@ -0,0 +1,62 @@
+use actix_web::{web, HttpRequest, Responder};
+use futures_util::TryStreamExt;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::{
+        admin_action::{self, AdminActionKind},
+        event::EventKind,
+        webhook::{self, Webhook},
+    },
+    errors::Result,
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookEntry {
+    id: String,
+    url: String,
+    description: String,
+    events: Vec<EventKind>,
+    created_by: String,
+    created_at: u64,
+}
+
+impl From<Webhook> for WebhookEntry {
+    fn from(webhook: Webhook) -> Self {
+        WebhookEntry {
+            id: webhook.id,
+            url: webhook.url,
+            description: webhook.description,
+            events: webhook.events,
+            created_by: webhook.created_by,
+            created_at: webhook.created_at,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminWebhooksResponse {
+    webhooks: Vec<WebhookEntry>,
+}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    let webhooks = webhook::get_collection()
+        .find(doc! {})
+        .sort(doc! { "id": 1 })
+        .await?
+        .map_ok(WebhookEntry::from)
+        .try_collect()
+        .await?;
+    admin_action::record(&admin.id, AdminActionKind::ViewWebhooks, None, None).await;
+    Ok(web::Json(AdminWebhooksResponse { webhooks }))
+}
@@ -0,0 +1,108 @@
+use actix_web::{web, Responder};
+use mongodb::bson::doc;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    constants::AUTHORIZATION_CODE_TIMEOUT,
+    database::client,
+    errors::{Error, Result},
+    flows::Flow,
+    oidc::parse_scopes,
+    utilities::generate_continue_token_long,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Authorize {
+    client_id: String,
+    redirect_uri: String,
+    response_type: String,
+    scope: String,
+    state: Option<String>,
+    nonce: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorizeResponse {
+    redirect_uri: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PendingAuthorization {
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub user_id: String,
+    pub scopes: Vec<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: String,
+    pub auth_time: u64,
+}
+
+pub static PENDING_AUTHORIZATIONS: Flow<PendingAuthorization> =
+    Flow::new("authorization", AUTHORIZATION_CODE_TIMEOUT);
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    authorize: web::Json<Authorize>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let authorize = authorize.into_inner();
+    let client = client::get_collection()
+        .find_one(doc! {
+            "id": &authorize.client_id
+        })
+        .await?
+        .ok_or(Error::InvalidClient)?;
+    if !client.redirect_uris.contains(&authorize.redirect_uri) {
+        return Err(Error::InvalidRedirectUri);
+    }
+    if authorize.response_type != "code" {
+        return Err(Error::UnsupportedResponseType);
+    }
+    let scopes = parse_scopes(&authorize.scope)?;
+    // plain offers no protection against an intercepted authorization request
+    let code_challenge_method = authorize
+        .code_challenge_method
+        .unwrap_or("S256".to_string());
+    if authorize.code_challenge.is_some() && code_challenge_method != "S256" {
+        return Err(Error::InvalidGrant);
+    }
+    // public clients cannot authenticate at the token endpoint
+    if client.secret.is_none() && authorize.code_challenge.is_none() {
+        return Err(Error::InvalidGrant);
+    }
+    let mut redirect_uri =
+        Url::parse(&authorize.redirect_uri).map_err(|_| Error::InvalidRedirectUri)?;
+    let code = generate_continue_token_long();
+    PENDING_AUTHORIZATIONS
+        .insert(
+            &code,
+            &PendingAuthorization {
+                client_id: client.id,
+                redirect_uri: authorize.redirect_uri,
+                user_id: jwt.jwt_content.id,
+                scopes,
+                nonce: authorize.nonce,
+                code_challenge: authorize.code_challenge,
+                code_challenge_method,
+                auth_time: (jwt.jwt_content.issued_at / 1000) as u64,
+            },
+        )
+        .await?;
+    {
+        let mut query = redirect_uri.query_pairs_mut();
+        query.append_pair("code", &code);
+        if let Some(state) = &authorize.state {
+            query.append_pair("state", state);
+        }
+    }
+    Ok(web::Json(AuthorizeResponse {
+        redirect_uri: redirect_uri.to_string(),
+    }))
+}
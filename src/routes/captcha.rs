@@ -0,0 +1,34 @@
+use actix_web::{web, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    captcha::{create_challenge, CaptchaProvider},
+    environment::{CAPTCHA_PROVIDER, CAPTCHA_SITE_KEY},
+    errors::Result,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptchaResponse {
+    provider: CaptchaProvider,
+    site_key: Option<String>,
+    // proof of work only: find a nonce so SHA-256 of `challenge:nonce` starts
+    // with `difficulty` zero bits
+    challenge: Option<String>,
+    difficulty: Option<u32>,
+}
+
+pub async fn handle() -> Result<impl Responder> {
+    let (challenge, difficulty) = if *CAPTCHA_PROVIDER == CaptchaProvider::ProofOfWork {
+        let (challenge, difficulty) = create_challenge().await?;
+        (Some(challenge), Some(difficulty))
+    } else {
+        (None, None)
+    };
+    Ok(web::Json(CaptchaResponse {
+        provider: *CAPTCHA_PROVIDER,
+        site_key: CAPTCHA_SITE_KEY.clone(),
+        challenge,
+        difficulty,
+    }))
+}
@@ -0,0 +1,69 @@
+use actix_web::{web, Responder};
+use mongodb::bson::doc;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::{
+    authenticate::Authenticate,
+    database::{
+        client::{self, Client},
+        user,
+    },
+    errors::{Error, Result},
+    utilities::{generate_continue_token_long, hash_secret, validate_escalation},
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateClient {
+    name: String,
+    redirect_uris: Vec<String>,
+    // public clients (e.g. single-page apps) get no secret and must use PKCE
+    confidential: bool,
+    escalation_token: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateClientResponse {
+    id: String,
+    secret: Option<String>,
+}
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    create_client: web::Json<CreateClient>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let create_client = create_client.into_inner();
+    let user_id = validate_escalation(create_client.escalation_token, jwt.session_id).await?;
+    let user = user::get_collection()
+        .find_one(doc! {
+            "id": &user_id
+        })
+        .await?
+        .ok_or(Error::DatabaseError)?;
+    if !user.platform_administrator {
+        return Err(Error::MissingPermission);
+    }
+    for uri in &create_client.redirect_uris {
+        Url::parse(uri).map_err(|_| Error::InvalidRedirectUri)?;
+    }
+    let secret = if create_client.confidential {
+        Some(generate_continue_token_long())
+    } else {
+        None
+    };
+    let id = Ulid::new().to_string();
+    client::get_collection()
+        .insert_one(Client {
+            id: id.clone(),
+            secret: secret.as_deref().map(hash_secret),
+            name: create_client.name.trim().to_string(),
+            redirect_uris: create_client.redirect_uris,
+            created_by: user_id,
+        })
+        .await?;
+    Ok(web::Json(CreateClientResponse { id, secret }))
+}
@@ -16,6 +16,7 @@ pub struct CurrentUserResponse {
     email: String,
     username: String,
     mfa_enabled: bool,
+    locale: Option<String>,
     display_name: String,
     description: String,
     website: String,
@@ -46,6 +47,7 @@ pub async fn handle(jwt: web::ReqData<Result<Authenticate>>) -> Result<impl Resp
         id: jwt.jwt_content.id,
         email: result.email,
         mfa_enabled: result.mfa_enabled,
+        locale: result.locale,
         username: result.username,
         website: profile_result.website,
     }))
@@ -0,0 +1,5 @@
+use actix_files::NamedFile;
+
+pub async fn handle() -> std::io::Result<NamedFile> {
+    NamedFile::open("assets/default.png")
+}
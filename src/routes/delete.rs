@@ -1,10 +1,14 @@
-use actix_web::{web, Responder};
+use actix_web::{web, HttpRequest, Responder};
 use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     authenticate::Authenticate,
-    database::{files::File, passkey, profile, session, user},
+    database::{
+        audit_event::{self, AuditEventKind},
+        user,
+    },
+    deletion,
     errors::{Error, Result},
     utilities::validate_escalation,
 };
@@ -16,45 +20,33 @@ pub struct Delete {
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct DeleteResponse {}
+pub struct DeleteResponse {
+    // seconds; the account can be restored until then
+    purge_at: u64,
+}
 
-// TODO: security concerns? potentially add a grace period
 pub async fn handle(
+    req: HttpRequest,
     jwt: web::ReqData<Result<Authenticate>>,
     delete: web::Json<Delete>,
 ) -> Result<impl Responder> {
     let jwt = jwt.into_inner()?;
-    validate_escalation(delete.escalation_token.clone(), jwt.jwt).await?;
-    let collection = user::get_collection();
-    let sessions = session::get_collection();
-    sessions
-        .delete_many(doc! { "user_id": &jwt.jwt_content.id })
-        .await?;
-    collection
-        .delete_one(doc! {
-            "id": &jwt.jwt_content.id
-        })
-        .await?;
-    let profile = profile::get_collection()
+    validate_escalation(delete.escalation_token.clone(), jwt.session_id.clone()).await?;
+    let user = user::get_collection()
         .find_one(doc! {
-            "id": &jwt.jwt_content.id,
+            "id": &jwt.jwt_content.id
         })
         .await?
         .ok_or(Error::DatabaseError)?;
-    if let Some(avatar) = profile.avatar {
-        if let Ok(avatar) = File::get(&avatar).await {
-            avatar.detach().await?;
-        }
-    }
-    profile::get_collection()
-        .delete_one(doc! {
-            "id": &jwt.jwt_content.id
-        })
-        .await?;
-    passkey::get_collection()
-        .delete_many(doc! {
-            "user_id": jwt.jwt_content.id
-        })
-        .await?;
-    Ok(web::Json(DeleteResponse {}))
+    let purge_at = deletion::schedule(&req, &user).await?;
+    audit_event::record(
+        &req,
+        AuditEventKind::DeletionScheduled,
+        Some(&jwt.jwt_content.id),
+        &jwt.jwt_content.id,
+        Some(&jwt.session_id),
+        None,
+    )
+    .await;
+    Ok(web::Json(DeleteResponse { purge_at }))
 }
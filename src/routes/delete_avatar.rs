@@ -1 +1,48 @@
-// TODO: wait until CDN is implemented
+use actix_web::{web, Responder};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    authenticate::Authenticate,
+    database::{
+        event::{self, EventKind},
+        profile,
+    },
+    errors::{Error, Result},
+};
+
+use super::update_avatar::detach_avatar;
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteAvatarResponse {}
+
+pub async fn handle(jwt: web::ReqData<Result<Authenticate>>) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let collection = profile::get_collection();
+    let profile = collection
+        .find_one(doc! {"id": &jwt.jwt_content.id})
+        .await?
+        .ok_or(Error::DatabaseError)?;
+    collection
+        .update_one(
+            doc! {"id": &jwt.jwt_content.id},
+            doc! {
+                "$set": {
+                    "avatar": None::<String>
+                }
+            },
+        )
+        .await?;
+    if profile.avatar.is_some() {
+        event::publish(
+            EventKind::UserUpdated,
+            &jwt.jwt_content.id,
+            Some(json!({ "fields": ["avatar"] })),
+        )
+        .await;
+    }
+    detach_avatar(profile.avatar).await?;
+    Ok(web::Json(DeleteAvatarResponse {}))
+}
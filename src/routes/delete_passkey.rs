@@ -1,9 +1,16 @@
-use actix_web::{web, Responder};
+use actix_web::{web, HttpRequest, Responder};
 use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    authenticate::Authenticate, database::passkey, errors::Result, utilities::validate_escalation,
+    authenticate::Authenticate,
+    database::{
+        audit_event::{self, AuditEventKind},
+        passkey, user,
+    },
+    errors::{Error, Result},
+    notifications::{notify, SecurityNotice},
+    utilities::validate_escalation,
 };
 
 #[derive(Deserialize, Serialize)]
@@ -13,17 +20,48 @@ pub struct DeletePasskey {
 }
 
 pub async fn handle(
+    req: HttpRequest,
     jwt: web::ReqData<Result<Authenticate>>,
     passkey_id: web::Path<String>,
     delete_passkey: web::Json<DeletePasskey>,
 ) -> Result<impl Responder> {
     let jwt = jwt.into_inner()?;
-    validate_escalation(delete_passkey.escalation_token.clone(), jwt.jwt).await?;
-    passkey::get_collection()
-        .delete_one(doc! {
-            "id": &passkey_id.into_inner(),
-            "user_id": jwt.jwt_content.id,
+    validate_escalation(
+        delete_passkey.escalation_token.clone(),
+        jwt.session_id.clone(),
+    )
+    .await?;
+    let passkey_id = passkey_id.into_inner();
+    let deleted = passkey::get_collection()
+        .find_one_and_delete(doc! {
+            "id": &passkey_id,
+            "user_id": &jwt.jwt_content.id,
         })
         .await?;
+    if let Some(deleted) = deleted {
+        audit_event::record(
+            &req,
+            AuditEventKind::PasskeyDeleted,
+            Some(&jwt.jwt_content.id),
+            &jwt.jwt_content.id,
+            Some(&jwt.session_id),
+            Some(passkey_id),
+        )
+        .await;
+        let user = user::get_collection()
+            .find_one(doc! {
+                "id": &jwt.jwt_content.id
+            })
+            .await?
+            .ok_or(Error::DatabaseError)?;
+        notify(
+            &req,
+            &user,
+            SecurityNotice::PasskeyRemoved {
+                name: deleted.friendly_name,
+            },
+        )
+        .await;
+    }
     Ok(web::Json("null"))
 }
@@ -0,0 +1,63 @@
+use actix_web::{web, HttpRequest, Responder};
+use async_std::task;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::{
+        audit_event::{self, AuditEventKind},
+        export as data_export, user,
+    },
+    errors::{Error, Result},
+    export,
+    templates::resolve_locale,
+    utilities::validate_escalation,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Export {
+    escalation_token: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportResponse {
+    // pass to GET /user/export/{id} once the user has been emailed
+    id: String,
+}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    export: web::Json<Export>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let user_id =
+        validate_escalation(export.into_inner().escalation_token, jwt.session_id.clone()).await?;
+    // an archive already being assembled will include everything a new one would
+    if let Some(pending) = data_export::get_pending(&user_id).await? {
+        return Ok(web::Json(ExportResponse { id: pending.id }));
+    }
+    let user = user::get_collection()
+        .find_one(doc! {
+            "id": &user_id
+        })
+        .await?
+        .ok_or(Error::DatabaseError)?;
+    let pending = data_export::create(&user_id).await?;
+    let id = pending.id.clone();
+    let locale = resolve_locale(user.locale.as_deref(), Some(&req));
+    task::spawn(export::prepare(pending, user, locale));
+    audit_event::record(
+        &req,
+        AuditEventKind::DataExported,
+        Some(&user_id),
+        &user_id,
+        Some(&jwt.session_id),
+        None,
+    )
+    .await;
+    Ok(web::Json(ExportResponse { id }))
+}
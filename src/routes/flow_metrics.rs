@@ -0,0 +1,28 @@
+use actix_web::{web, HttpRequest, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::admin_action::{self, AdminActionKind},
+    errors::Result,
+    flows::{get_metrics, FlowMetrics},
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlowMetricsResponse {
+    flows: Vec<FlowMetrics>,
+}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(&req, jwt.session_id).await?;
+    admin_action::record(&admin.id, AdminActionKind::ViewFlowMetrics, None, None).await;
+    Ok(web::Json(FlowMetricsResponse {
+        flows: get_metrics(),
+    }))
+}
@@ -1,23 +1,30 @@
-use actix_web::{web, Responder};
+use actix_web::{web, HttpRequest, Responder};
 use async_std::task;
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
-use dashmap::DashMap;
-use lazy_static::lazy_static;
 use mongodb::bson::{self, doc, Binary};
 use opaque_ke::{RegistrationRequest, RegistrationUpload};
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    constants::{CONTINUE_TIMEOUT, SHORT_CONTINUE_TIMEOUT},
+    database::audit_event::{self, AuditEventKind},
     errors::{Error, Result},
+    flows::Flow,
+    notifications::{notify, SecurityNotice},
     opaque::{begin_registration, finish_registration},
-    utilities::{generate_continue_token_long, get_time_secs, send_reset_email},
+    risk::{check_captcha, reset_requires_captcha},
+    templates::resolve_locale,
+    utilities::{generate_continue_token_long, send_reset_email},
 };
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE", tag = "stage")]
 pub enum Forgot {
+    #[serde(rename_all = "camelCase")]
     VerifyEmail {
         email: String,
+        // req'd from a new IP address, see risk::reset_requires_captcha
+        captcha_token: Option<String>,
     },
     #[serde(rename_all = "camelCase")]
     ResetPassword {
@@ -43,38 +50,46 @@ pub enum ForgotResponse {
     FinishReset {},
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PendingForgot {
-    pub time: u64,
     pub user_id: String,
     pub email: String,
 }
 
-lazy_static! {
-    pub static ref PENDING_FORGOTS1: DashMap<String, PendingForgot> = DashMap::new();
-    pub static ref PENDING_FORGOTS2: DashMap<String, PendingForgot> = DashMap::new();
-}
+pub static PENDING_FORGOTS1: Flow<PendingForgot> = Flow::new("forgot_email", CONTINUE_TIMEOUT);
+pub static PENDING_FORGOTS2: Flow<PendingForgot> = Flow::new("forgot", SHORT_CONTINUE_TIMEOUT);
 
-pub async fn handle(forgot: web::Json<Forgot>) -> Result<impl Responder> {
+pub async fn handle(req: HttpRequest, forgot: web::Json<Forgot>) -> Result<impl Responder> {
     let forgot = forgot.into_inner();
     match forgot {
-        Forgot::VerifyEmail { email } => {
+        Forgot::VerifyEmail {
+            email,
+            captcha_token,
+        } => {
             let collection = crate::database::user::get_collection();
             let result = collection
                 .find_one(doc! {
                     "email": email.clone()
                 })
                 .await?;
+            let captcha_required = reset_requires_captcha(&req, result.as_ref()).await?;
+            check_captcha(&req, captcha_token, captcha_required).await?;
             if let Some(result) = result {
                 let token = generate_continue_token_long();
-                task::spawn(send_reset_email(email.clone(), token.clone()));
-                PENDING_FORGOTS1.insert(
-                    token,
-                    PendingForgot {
-                        time: get_time_secs(),
-                        user_id: result.id,
-                        email,
-                    },
-                );
+                task::spawn(send_reset_email(
+                    email.clone(),
+                    resolve_locale(result.locale.as_deref(), Some(&req)),
+                    token.clone(),
+                ));
+                PENDING_FORGOTS1
+                    .insert(
+                        &token,
+                        &PendingForgot {
+                            user_id: result.id,
+                            email,
+                        },
+                    )
+                    .await?;
             }
             Ok(web::Json(ForgotResponse::VerifyEmail {}))
         }
@@ -82,31 +97,26 @@ pub async fn handle(forgot: web::Json<Forgot>) -> Result<impl Responder> {
             continue_token,
             message,
         } => {
-            let forgot_session = PENDING_FORGOTS1.get(&continue_token);
+            let forgot_session = PENDING_FORGOTS1.get(&continue_token).await?;
             let Some(forgot_session) = forgot_session else {
                 return Err(Error::SessionExpired);
             };
-            if get_time_secs() - forgot_session.time > 3600 {
-                drop(forgot_session);
-                PENDING_FORGOTS1.remove(&continue_token);
-                return Err(Error::SessionExpired);
-            }
             let result = begin_registration(
                 forgot_session.email.clone(),
                 RegistrationRequest::deserialize(&BASE64.decode(message)?)?,
             )
             .await?;
-            PENDING_FORGOTS1.remove(&continue_token);
+            PENDING_FORGOTS1.complete(&continue_token).await?;
             let new_continue_token = generate_continue_token_long();
-            PENDING_FORGOTS2.insert(
-                new_continue_token.clone(),
-                PendingForgot {
-                    time: get_time_secs(),
-                    user_id: forgot_session.user_id.clone(),
-                    email: forgot_session.email.clone(),
-                },
-            );
-            drop(forgot_session);
+            PENDING_FORGOTS2
+                .insert(
+                    &new_continue_token,
+                    &PendingForgot {
+                        user_id: forgot_session.user_id,
+                        email: forgot_session.email,
+                    },
+                )
+                .await?;
             Ok(web::Json(ForgotResponse::ResetPassword {
                 continue_token: new_continue_token.clone(),
                 message: BASE64.encode(result),
@@ -116,13 +126,9 @@ pub async fn handle(forgot: web::Json<Forgot>) -> Result<impl Responder> {
             continue_token,
             message,
         } => {
-            let Some(session) = PENDING_FORGOTS2.get(&continue_token) else {
+            let Some(session) = PENDING_FORGOTS2.get(&continue_token).await? else {
                 return Err(Error::SessionExpired);
             };
-            if get_time_secs() - session.time > 600 {
-                PENDING_FORGOTS2.remove(&continue_token);
-                return Err(Error::SessionExpired);
-            }
             let password_data =
                 finish_registration(RegistrationUpload::deserialize(&BASE64.decode(message)?)?)?;
             let bin = Binary {
@@ -130,8 +136,8 @@ pub async fn handle(forgot: web::Json<Forgot>) -> Result<impl Responder> {
                 subtype: bson::spec::BinarySubtype::Generic,
             };
             let collection = crate::database::user::get_collection();
-            collection
-                .update_one(
+            let user = collection
+                .find_one_and_update(
                     doc! {
                         "id": session.user_id.clone()
                     },
@@ -141,7 +147,33 @@ pub async fn handle(forgot: web::Json<Forgot>) -> Result<impl Responder> {
                         }
                     },
                 )
+                .await?
+                .ok_or(Error::UserNotFound)?;
+            // proving control of the email address is what a locked account waits for
+            collection
+                .update_one(
+                    doc! {
+                        "id": &session.user_id,
+                        "status.state": "locked"
+                    },
+                    doc! {
+                        "$set": {
+                            "status": { "state": "active" }
+                        }
+                    },
+                )
                 .await?;
+            audit_event::record(
+                &req,
+                AuditEventKind::PasswordReset,
+                Some(&session.user_id),
+                &session.user_id,
+                None,
+                None,
+            )
+            .await;
+            notify(&req, &user, SecurityNotice::PasswordChanged).await;
+            PENDING_FORGOTS2.complete(&continue_token).await?;
             Ok(web::Json(ForgotResponse::FinishReset {}))
         }
     }
@@ -0,0 +1,21 @@
+use actix_web::{web, Responder};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{authenticate::Authenticate, database::code, errors::Result};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetCodesResponse {
+    remaining: u64,
+}
+
+pub async fn handle(jwt: web::ReqData<Result<Authenticate>>) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let remaining = code::get_collection()
+        .count_documents(doc! {
+            "user_id": jwt.jwt_content.id
+        })
+        .await?;
+    Ok(web::Json(GetCodesResponse { remaining }))
+}
@@ -0,0 +1,33 @@
+use actix_web::{http::header, web, web::Bytes, HttpResponse};
+use futures_util::TryStreamExt;
+
+use crate::{
+    authenticate::Authenticate,
+    database::export::{self, ExportStatus},
+    errors::{Error, Result},
+};
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    id: web::Path<String>,
+) -> Result<HttpResponse> {
+    let jwt = jwt.into_inner()?;
+    let id = id.into_inner();
+    let Some(export) = export::get(&id, &jwt.jwt_content.id).await? else {
+        return Err(Error::ExportNotFound);
+    };
+    match export.status {
+        ExportStatus::Ready => {
+            let archive = export::read_archive(&id).await?.map_ok(Bytes::from);
+            Ok(HttpResponse::Ok()
+                .content_type("application/json")
+                .insert_header((
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"export-{}.json\"", id),
+                ))
+                .streaming(archive))
+        }
+        ExportStatus::Failed => Err(Error::ExportFailed),
+        ExportStatus::Pending => Err(Error::ExportNotReady),
+    }
+}
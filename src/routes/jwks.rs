@@ -0,0 +1,7 @@
+use actix_web::{web, Responder};
+
+use crate::{errors::Result, signing::get_jwks};
+
+pub async fn handle() -> Result<impl Responder> {
+    Ok(web::Json(get_jwks().await?))
+}
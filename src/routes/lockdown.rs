@@ -0,0 +1,68 @@
+use actix_web::{web, HttpRequest, Responder};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::LOCKDOWN_TIMEOUT,
+    database::{
+        audit_event::{self, AuditEventKind},
+        session, user,
+    },
+    errors::{Error, Result},
+    flows::Flow,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Lockdown {
+    token: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockdownResponse {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PendingLockdown {
+    pub user_id: String,
+}
+
+pub static PENDING_LOCKDOWNS: Flow<PendingLockdown> = Flow::new("lockdown", LOCKDOWN_TIMEOUT);
+
+// reached from the "this wasn't me" link in security notification emails; the account
+// stays locked until the user resets their password through /api/forgot
+pub async fn handle(req: HttpRequest, lockdown: web::Json<Lockdown>) -> Result<impl Responder> {
+    let token = lockdown.into_inner().token;
+    let Some(lockdown) = PENDING_LOCKDOWNS.get(&token).await? else {
+        return Err(Error::SessionExpired);
+    };
+    // suspended accounts stay suspended, but are still signed out
+    user::get_collection()
+        .update_one(
+            doc! {
+                "id": &lockdown.user_id,
+                "status.state": { "$ne": "suspended" }
+            },
+            doc! {
+                "$set": {
+                    "status": { "state": "locked" }
+                }
+            },
+        )
+        .await?;
+    session::revoke(doc! {
+        "user_id": &lockdown.user_id
+    })
+    .await?;
+    audit_event::record(
+        &req,
+        AuditEventKind::Lockdown,
+        Some(&lockdown.user_id),
+        &lockdown.user_id,
+        None,
+        None,
+    )
+    .await;
+    PENDING_LOCKDOWNS.complete(&token).await?;
+    Ok(web::Json(LockdownResponse {}))
+}
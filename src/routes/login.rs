@@ -1,27 +1,39 @@
-use actix_web::{web, Responder};
+use actix_web::{web, HttpRequest, Responder};
+use async_std::task;
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
-use dashmap::DashMap;
-use jsonwebtoken::{encode, EncodingKey, Header};
-use lazy_static::lazy_static;
 use mongodb::bson::doc;
 use opaque_ke::{CredentialFinalization, CredentialRequest, ServerLogin};
 use serde::{Deserialize, Serialize};
 use totp_rs::{Algorithm, Secret, TOTP};
-use ulid::Ulid;
 
 use crate::{
-    authenticate::{validate_token, UserJwt},
-    constants::{LONG_SESSION, SHORT_SESSION},
-    database::{self, session::Session, user::User},
-    environment::{JWT_SECRET, SERVICE_NAME},
+    authenticate::{create_session, validate_token},
+    constants::{CONTINUE_TIMEOUT, RECOVERY_CODES_WARNING},
+    database::{
+        self,
+        audit_event::{self, AuditEventKind},
+        code,
+        session::Session,
+        user::User,
+    },
+    deletion, encryption,
+    environment::SERVICE_NAME,
     errors::{Error, Result},
+    flows::Flow,
+    notifications::{is_new_device, notify, SecurityNotice},
     opaque::{begin_login, finish_login, Default},
-    utilities::{generate_continue_token_long, get_time_millis, get_time_secs},
+    risk::{
+        check_account_throttle, check_captcha, clear_account_failures, clear_login_failures,
+        login_requires_captcha, record_account_failure, record_login_failure, record_mfa_failure,
+    },
+    templates::resolve_locale,
+    utilities::{generate_continue_token_long, send_codes_low_email},
 };
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE", tag = "stage")]
 pub enum Login {
+    #[serde(rename_all = "camelCase")]
     BeginLogin {
         email: String,
         message: String,
@@ -29,6 +41,8 @@ pub enum Login {
         escalate: bool,
         // req'd if escalating an existing session
         token: Option<String>,
+        // req'd after repeated failures, see risk::login_requires_captcha
+        captcha_token: Option<String>,
     },
     #[serde(rename_all = "camelCase")]
     FinishLogin {
@@ -57,43 +71,126 @@ pub enum LoginResponse {
         mfa_enabled: bool,
         continue_token: Option<String>,
         token: Option<String>,
+        // not issued when escalating
+        refresh_token: Option<String>,
     },
+    #[serde(rename_all = "camelCase")]
     Mfa {
         token: String,
+        refresh_token: Option<String>,
     },
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PendingLogin {
-    pub time: u64,
-    pub user: User,
+    pub user_id: String,
     pub email: String,
-    pub data: ServerLogin<Default>,
+    // serialized ServerLogin<Default>
+    pub data: Vec<u8>,
     pub existing_session: Option<Session>,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PendingMfa {
-    pub time: u64,
-    pub user: User,
+    pub user_id: String,
     pub email: String,
     pub persist: Option<bool>,
     pub friendly_name: Option<String>,
     pub existing_session: Option<Session>,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ActiveEscalation {
     pub session_id: String,
     pub user_id: String,
-    pub time: u64,
     pub token: String,
 }
 
-lazy_static! {
-    pub static ref PENDING_LOGINS: DashMap<String, PendingLogin> = DashMap::new();
-    pub static ref PENDING_MFAS: DashMap<String, PendingMfa> = DashMap::new();
-    pub static ref ACTIVE_ESCALATIONS: DashMap<String, ActiveEscalation> = DashMap::new();
+pub static PENDING_LOGINS: Flow<PendingLogin> = Flow::new("login", CONTINUE_TIMEOUT);
+pub static PENDING_MFAS: Flow<PendingMfa> = Flow::new("login_mfa", CONTINUE_TIMEOUT);
+pub static ACTIVE_ESCALATIONS: Flow<ActiveEscalation> = Flow::new("escalation", CONTINUE_TIMEOUT);
+
+// escalates the existing session if there is one, otherwise creates a new session;
+// returns the access or escalation token and the refresh token of a new session
+pub async fn complete_login(
+    req: &HttpRequest,
+    user_id: String,
+    existing_session: Option<Session>,
+    friendly_name: Option<String>,
+    persist: Option<bool>,
+) -> Result<(String, Option<String>)> {
+    // re-read the user, as they may have been suspended since the login began
+    let user = database::user::get_collection()
+        .find_one(doc! {
+            "id": &user_id
+        })
+        .await?
+        .ok_or(Error::UserNotFound)?;
+    user.ensure_active()?;
+    clear_account_failures(&user_id).await?;
+    // signing in during the grace period keeps the account
+    if user.deletion.is_some() {
+        deletion::cancel(req, &user).await?;
+    }
+    if let Some(existing_session) = existing_session {
+        let token = generate_continue_token_long();
+        audit_event::record(
+            req,
+            AuditEventKind::Escalated,
+            Some(&user_id),
+            &user_id,
+            Some(&existing_session.id),
+            None,
+        )
+        .await;
+        ACTIVE_ESCALATIONS
+            .insert(
+                &token,
+                &ActiveEscalation {
+                    session_id: existing_session.id,
+                    token: token.clone(),
+                    user_id,
+                },
+            )
+            .await?;
+        return Ok((token, None));
+    }
+    // checked before the new session exists, as it would make every device known
+    let new_device = is_new_device(req, &user_id).await?;
+    let tokens = create_session(
+        user_id.clone(),
+        friendly_name.unwrap_or("Unknown".to_owned()),
+        persist.unwrap_or(false),
+        req,
+    )
+    .await?;
+    audit_event::record(
+        req,
+        AuditEventKind::Login,
+        Some(&user_id),
+        &user_id,
+        Some(&tokens.session_id),
+        None,
+    )
+    .await;
+    if new_device {
+        notify(req, &user, SecurityNotice::NewSignIn).await;
+    }
+    Ok((tokens.token, Some(tokens.refresh_token)))
+}
+
+// re-read when a login continues rather than kept in the flow, which may be stored
+// in the database
+async fn get_user(user_id: &str) -> Result<User> {
+    database::user::get_collection()
+        .find_one(doc! {
+            "id": user_id
+        })
+        .await?
+        .ok_or(Error::SessionExpired)
 }
 
-pub async fn handle(login: web::Json<Login>) -> Result<impl Responder> {
+pub async fn handle(req: HttpRequest, login: web::Json<Login>) -> Result<impl Responder> {
     let login = login.into_inner();
     match login {
         Login::BeginLogin {
@@ -101,16 +198,19 @@ pub async fn handle(login: web::Json<Login>) -> Result<impl Responder> {
             message,
             escalate,
             token,
+            captcha_token,
         } => {
+            let captcha_required = login_requires_captcha(&req, &email).await?;
+            check_captcha(&req, captcha_token, captcha_required).await?;
             let existing_session = if escalate {
                 let Some(token) = token else {
                     return Err(Error::MissingToken);
                 };
-                validate_token(&token.clone()).await?;
+                let authenticate = validate_token(&token).await?;
                 let collection = crate::database::session::get_collection();
                 let session = collection
                     .find_one(doc! {
-                        "token": token.clone()
+                        "id": authenticate.session_id
                     })
                     .await?
                     .ok_or(Error::SessionExpired)?;
@@ -124,6 +224,8 @@ pub async fn handle(login: web::Json<Login>) -> Result<impl Responder> {
                     "email": email.clone()
                 })
                 .await?;
+            // the account throttle is only checked once the login continues, as checking
+            // it here would reveal which addresses have accounts
             let password_data = user.clone().map(|x| x.password_data);
             let (data, state) = begin_login(
                 email.clone(),
@@ -134,13 +236,14 @@ pub async fn handle(login: web::Json<Login>) -> Result<impl Responder> {
             let continue_token = generate_continue_token_long();
             if let Some(user) = user {
                 let pending_login = PendingLogin {
-                    time: get_time_secs(),
-                    user,
+                    user_id: user.id,
                     email,
-                    data: state,
+                    data: state.serialize().to_vec(),
                     existing_session,
                 };
-                PENDING_LOGINS.insert(continue_token.clone(), pending_login);
+                PENDING_LOGINS
+                    .insert(&continue_token, &pending_login)
+                    .await?;
             }
             Ok(web::Json(LoginResponse::BeginLogin {
                 continue_token,
@@ -153,21 +256,35 @@ pub async fn handle(login: web::Json<Login>) -> Result<impl Responder> {
             persist,
             friendly_name,
         } => {
-            let pending_login = PENDING_LOGINS.get(&continue_token);
+            let pending_login = PENDING_LOGINS.get(&continue_token).await?;
             let pending_login = match pending_login {
                 Some(pending_login) => pending_login,
                 None => return Err(Error::SessionExpired),
             };
-            if get_time_secs() - pending_login.time > 3600 {
-                drop(pending_login);
-                PENDING_LOGINS.remove(&continue_token);
-                return Err(Error::SessionExpired);
-            }
-            finish_login(
-                pending_login.data.clone(),
+            check_account_throttle(&pending_login.user_id).await?;
+            let user = get_user(&pending_login.user_id).await?;
+            let result = finish_login(
+                ServerLogin::<Default>::deserialize(&pending_login.data)?,
                 CredentialFinalization::deserialize(&BASE64.decode(message)?)?,
-            )?;
-            let user = pending_login.user.clone();
+            );
+            if let Err(error) = result {
+                // each login attempt gets one try at the password
+                PENDING_LOGINS.complete(&continue_token).await?;
+                record_login_failure(&req, &pending_login.email).await?;
+                record_account_failure(&req, &user).await?;
+                audit_event::record(
+                    &req,
+                    AuditEventKind::LoginFailed,
+                    None,
+                    &user.id,
+                    None,
+                    Some("incorrect password".to_string()),
+                )
+                .await;
+                return Err(error);
+            }
+            clear_login_failures(&pending_login.email).await?;
+            user.ensure_active()?;
             if let Some(existing_session) = pending_login.existing_session.clone() {
                 if user.id != existing_session.user_id {
                     return Err(Error::UserMismatch);
@@ -176,64 +293,35 @@ pub async fn handle(login: web::Json<Login>) -> Result<impl Responder> {
             if user.mfa_enabled {
                 let new_continue_token = generate_continue_token_long();
                 let mfa_session = PendingMfa {
-                    time: get_time_secs(),
-                    user,
+                    user_id: user.id,
                     email: pending_login.email.clone(),
                     persist,
                     friendly_name,
                     existing_session: pending_login.existing_session.clone(),
                 };
-                PENDING_MFAS.insert(new_continue_token.clone(), mfa_session);
-                drop(pending_login);
-                PENDING_LOGINS.remove(&continue_token);
+                PENDING_MFAS
+                    .insert(&new_continue_token, &mfa_session)
+                    .await?;
+                PENDING_LOGINS.complete(&continue_token).await?;
                 Ok(web::Json(LoginResponse::FinishLogin {
                     mfa_enabled: true,
                     continue_token: Some(new_continue_token),
                     token: None,
+                    refresh_token: None,
                 }))
             } else {
-                let persist = persist.unwrap_or(false);
-                let millis = get_time_millis();
-                let expires_at = if persist {
-                    millis + LONG_SESSION
-                } else {
-                    millis + SHORT_SESSION
-                };
-                let token = encode(
-                    &Header::default(),
-                    &UserJwt {
-                        id: user.id.clone(),
-                        issued_at: millis,
-                        expires_at,
-                    },
-                    &EncodingKey::from_secret(JWT_SECRET.as_ref()),
+                let (token, refresh_token) = complete_login(
+                    &req,
+                    user.id,
+                    pending_login.existing_session,
+                    friendly_name,
+                    persist,
                 )
-                .expect("Unexpected error: failed to encode token");
-                if let Some(existing_session) = pending_login.existing_session.clone() {
-                    ACTIVE_ESCALATIONS.insert(
-                        token.clone(),
-                        ActiveEscalation {
-                            session_id: existing_session.id.clone(),
-                            time: get_time_secs(),
-                            token: token.clone(),
-                            user_id: user.id.clone(),
-                        },
-                    );
-                } else {
-                    let sid = Ulid::new().to_string();
-                    let session = Session {
-                        id: sid,
-                        token: token.clone(),
-                        friendly_name: friendly_name.unwrap_or("Unknown".to_owned()),
-                        user_id: user.id.clone(),
-                    };
-                    let sessions = crate::database::session::get_collection();
-                    sessions.insert_one(session).await?;
-                }
-                drop(pending_login);
-                PENDING_LOGINS.remove(&continue_token);
+                .await?;
+                PENDING_LOGINS.complete(&continue_token).await?;
                 Ok(web::Json(LoginResponse::FinishLogin {
                     token: Some(token),
+                    refresh_token,
                     continue_token: None,
                     mfa_enabled: false,
                 }))
@@ -243,17 +331,16 @@ pub async fn handle(login: web::Json<Login>) -> Result<impl Responder> {
             code,
             continue_token,
         } => {
-            let mfa_session = PENDING_MFAS.get(&continue_token);
+            let mfa_session = PENDING_MFAS.get(&continue_token).await?;
             let Some(mfa_session) = mfa_session else {
                 return Err(Error::SessionExpired);
             };
-            if get_time_secs() - mfa_session.time > 3600 {
-                drop(mfa_session);
-                PENDING_MFAS.remove(&continue_token);
-                return Err(Error::SessionExpired);
-            }
+            check_account_throttle(&mfa_session.user_id).await?;
+            let user = get_user(&mfa_session.user_id).await?;
 
-            let secret = Secret::Encoded(mfa_session.user.mfa_secret.clone().unwrap());
+            let secret =
+                encryption::open(user.mfa_secret.as_ref().ok_or(Error::DatabaseError)?).await?;
+            let secret = Secret::Encoded(secret);
             let totp = TOTP::new(
                 Algorithm::SHA256,
                 8,
@@ -268,67 +355,45 @@ pub async fn handle(login: web::Json<Login>) -> Result<impl Responder> {
                 .generate_current()
                 .expect("Unexpected error: failed to generate code");
             if current_code != code {
-                let codes = database::code::get_collection();
-                let code = codes
-                    .find_one(doc! {
-                        "code": code,
-                        "user_id": &mfa_session.user.id
-                    })
-                    .await?;
-                let Some(code) = code else {
+                if !code::redeem(&user.id, &code).await? {
+                    record_mfa_failure(&req, &user, &continue_token).await?;
+                    audit_event::record(
+                        &req,
+                        AuditEventKind::LoginFailed,
+                        None,
+                        &user.id,
+                        None,
+                        Some("incorrect MFA code".to_string()),
+                    )
+                    .await;
                     return Err(Error::IncorrectCode);
-                };
-                codes
-                    .delete_one(doc! {
-                        "code": code.code
+                }
+                let remaining = code::get_collection()
+                    .count_documents(doc! {
+                        "user_id": &user.id
                     })
                     .await?;
+                if remaining <= RECOVERY_CODES_WARNING {
+                    task::spawn(send_codes_low_email(
+                        user.email.clone(),
+                        resolve_locale(user.locale.as_deref(), Some(&req)),
+                        remaining,
+                    ));
+                }
             }
-            let persist = mfa_session.persist.unwrap_or(false);
-            let millis = get_time_millis();
-            let expires_at = if persist {
-                millis + 2592000000
-            } else {
-                millis + 604800000
-            };
-            let id = mfa_session.user.id.clone();
-            let token = encode(
-                &Header::default(),
-                &UserJwt {
-                    id: id.clone(),
-                    issued_at: millis,
-                    expires_at,
-                },
-                &EncodingKey::from_secret(JWT_SECRET.as_ref()),
+            let (token, refresh_token) = complete_login(
+                &req,
+                user.id,
+                mfa_session.existing_session,
+                mfa_session.friendly_name,
+                mfa_session.persist,
             )
-            .expect("Unexpected error: failed to encode token");
-            if let Some(existing_session) = mfa_session.existing_session.clone() {
-                ACTIVE_ESCALATIONS.insert(
-                    token.clone(),
-                    ActiveEscalation {
-                        session_id: existing_session.id.clone(),
-                        time: get_time_secs(),
-                        token: token.clone(),
-                        user_id: id.clone(),
-                    },
-                );
-            } else {
-                let sid = ulid::Ulid::new().to_string();
-                let session = Session {
-                    id: sid,
-                    token: token.clone(),
-                    friendly_name: mfa_session
-                        .friendly_name
-                        .clone()
-                        .unwrap_or("Unknown".to_owned()),
-                    user_id: id,
-                };
-                let sessions = crate::database::session::get_collection();
-                sessions.insert_one(session).await?;
-            }
-            drop(mfa_session);
-            PENDING_MFAS.remove(&continue_token);
-            Ok(web::Json(LoginResponse::Mfa { token }))
+            .await?;
+            PENDING_MFAS.complete(&continue_token).await?;
+            Ok(web::Json(LoginResponse::Mfa {
+                token,
+                refresh_token,
+            }))
         }
     }
 }
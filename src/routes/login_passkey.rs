@@ -1,13 +1,9 @@
 use actix_web::{
     web::{self, Data},
-    Responder,
+    HttpRequest, Responder,
 };
-use dashmap::DashMap;
-use jsonwebtoken::{encode, EncodingKey, Header};
-use lazy_static::lazy_static;
 use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
-use ulid::Ulid;
 use webauthn_rs::{
     prelude::{
         DiscoverableAuthentication, DiscoverableKey, PublicKeyCredential, RequestChallengeResponse,
@@ -16,15 +12,15 @@ use webauthn_rs::{
 };
 
 use crate::{
-    authenticate::UserJwt,
-    constants::{LONG_SESSION, SHORT_SESSION},
+    authenticate::validate_token,
+    constants::CONTINUE_TIMEOUT,
     database::{self, passkey::get_collection, session::Session},
-    environment::JWT_SECRET,
     errors::{Error, Result},
-    utilities::{generate_continue_token_long, get_time_millis, get_time_secs},
+    flows::Flow,
+    utilities::generate_continue_token_long,
 };
 
-use super::login::{ActiveEscalation, ACTIVE_ESCALATIONS};
+use super::login::complete_login;
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE", tag = "stage")]
@@ -51,22 +47,27 @@ pub enum LoginResponse {
         continue_token: String,
         message: RequestChallengeResponse,
     },
+    #[serde(rename_all = "camelCase")]
     FinishLogin {
         token: String,
+        // not issued when escalating
+        refresh_token: Option<String>,
     },
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PendingLogin {
-    pub time: u64,
     pub data: DiscoverableAuthentication,
     pub existing_session: Option<Session>,
 }
 
-lazy_static! {
-    pub static ref PENDING_LOGINS: DashMap<String, PendingLogin> = DashMap::new();
-}
+pub static PENDING_LOGINS: Flow<PendingLogin> = Flow::new("login_passkey", CONTINUE_TIMEOUT);
 
-pub async fn handle(login: web::Json<Login>, webauthn: Data<Webauthn>) -> Result<impl Responder> {
+pub async fn handle(
+    req: HttpRequest,
+    login: web::Json<Login>,
+    webauthn: Data<Webauthn>,
+) -> Result<impl Responder> {
     let login = login.into_inner();
     match login {
         Login::BeginLogin { escalate, token } => {
@@ -74,10 +75,11 @@ pub async fn handle(login: web::Json<Login>, webauthn: Data<Webauthn>) -> Result
                 let Some(token) = token else {
                     return Err(Error::MissingToken);
                 };
+                let authenticate = validate_token(&token).await?;
                 let collection = crate::database::session::get_collection();
                 let session = collection
                     .find_one(doc! {
-                        "token": token.clone()
+                        "id": authenticate.session_id
                     })
                     .await?
                     .ok_or(Error::SessionExpired)?;
@@ -87,14 +89,15 @@ pub async fn handle(login: web::Json<Login>, webauthn: Data<Webauthn>) -> Result
             };
             let (rcr, auth_state) = webauthn.start_discoverable_authentication()?;
             let continue_token = generate_continue_token_long();
-            PENDING_LOGINS.insert(
-                continue_token.clone(),
-                PendingLogin {
-                    time: get_time_secs(),
-                    data: auth_state,
-                    existing_session,
-                },
-            );
+            PENDING_LOGINS
+                .insert(
+                    &continue_token,
+                    &PendingLogin {
+                        data: auth_state,
+                        existing_session,
+                    },
+                )
+                .await?;
             Ok(web::Json(LoginResponse::BeginLogin {
                 continue_token,
                 message: rcr,
@@ -106,16 +109,11 @@ pub async fn handle(login: web::Json<Login>, webauthn: Data<Webauthn>) -> Result
             persist,
             friendly_name,
         } => {
-            let pending_login = PENDING_LOGINS.get(&continue_token);
+            let pending_login = PENDING_LOGINS.get(&continue_token).await?;
             let pending_login = match pending_login {
                 Some(pending_login) => pending_login,
                 None => return Err(Error::SessionExpired),
             };
-            if get_time_secs() - pending_login.time > 3600 {
-                drop(pending_login);
-                PENDING_LOGINS.remove(&continue_token);
-                return Err(Error::SessionExpired);
-            }
             let passkey = get_collection()
                 .find_one(doc! {
                     "credential_id": &message.id
@@ -124,7 +122,7 @@ pub async fn handle(login: web::Json<Login>, webauthn: Data<Webauthn>) -> Result
                 .ok_or(Error::CredentialError)?;
             webauthn.finish_discoverable_authentication(
                 &message,
-                pending_login.data.clone(),
+                pending_login.data,
                 &[DiscoverableKey::from(passkey.credential)],
             )?;
             let user = database::user::get_collection()
@@ -138,47 +136,19 @@ pub async fn handle(login: web::Json<Login>, webauthn: Data<Webauthn>) -> Result
                     return Err(Error::UserMismatch);
                 }
             }
-            let persist = persist.unwrap_or(false);
-            let millis = get_time_millis();
-            let expires_at = if persist {
-                millis + LONG_SESSION
-            } else {
-                millis + SHORT_SESSION
-            };
-            let token = encode(
-                &Header::default(),
-                &UserJwt {
-                    id: user.id.clone(),
-                    issued_at: millis,
-                    expires_at,
-                },
-                &EncodingKey::from_secret(JWT_SECRET.as_ref()),
+            let (token, refresh_token) = complete_login(
+                &req,
+                user.id,
+                pending_login.existing_session,
+                friendly_name,
+                persist,
             )
-            .expect("Unexpected error: failed to encode token");
-            if let Some(existing_session) = pending_login.existing_session.clone() {
-                ACTIVE_ESCALATIONS.insert(
-                    token.clone(),
-                    ActiveEscalation {
-                        session_id: existing_session.id.clone(),
-                        time: get_time_secs(),
-                        token: token.clone(),
-                        user_id: user.id.clone(),
-                    },
-                );
-            } else {
-                let sid = Ulid::new().to_string();
-                let session = Session {
-                    id: sid,
-                    token: token.clone(),
-                    friendly_name: friendly_name.unwrap_or("Unknown".to_owned()),
-                    user_id: user.id.clone(),
-                };
-                let sessions = crate::database::session::get_collection();
-                sessions.insert_one(session).await?;
-            }
-            drop(pending_login);
-            PENDING_LOGINS.remove(&continue_token);
-            Ok(web::Json(LoginResponse::FinishLogin { token }))
+            .await?;
+            PENDING_LOGINS.complete(&continue_token).await?;
+            Ok(web::Json(LoginResponse::FinishLogin {
+                token,
+                refresh_token,
+            }))
         }
     }
 }
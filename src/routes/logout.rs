@@ -2,7 +2,7 @@ use actix_web::{web, Responder};
 use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
 
-use crate::{authenticate::Authenticate, database::session::get_collection, errors::Result};
+use crate::{authenticate::Authenticate, database::session, errors::Result};
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -10,7 +10,6 @@ pub struct LogoutResponse {}
 
 pub async fn handle(jwt: web::ReqData<Result<Authenticate>>) -> Result<impl Responder> {
     let jwt = jwt.into_inner()?;
-    let sessions = get_collection();
-    sessions.delete_one(doc! { "token": jwt.jwt }).await?;
+    session::revoke(doc! { "id": jwt.session_id }).await?;
     Ok(web::Json(LogoutResponse {}))
 }
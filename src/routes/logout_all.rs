@@ -1,21 +1,34 @@
-use actix_web::{web, Responder};
+use actix_web::{web, HttpRequest, Responder};
 use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
 
-use crate::{authenticate::Authenticate, database::session::get_collection, errors::Result};
+use crate::{
+    authenticate::Authenticate,
+    database::{session, user},
+    errors::{Error, Result},
+    notifications::{notify, SecurityNotice},
+};
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LogoutAllResponse {}
 
-pub async fn handle(jwt: web::ReqData<Result<Authenticate>>) -> Result<impl Responder> {
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+) -> Result<impl Responder> {
     let jwt = jwt.into_inner()?;
-    let sessions = get_collection();
-    sessions
-        .delete_many(doc! {
-            "user_id": &jwt.jwt_content.id,
-            "token": doc! { "$ne": jwt.jwt }
+    session::revoke(doc! {
+        "user_id": &jwt.jwt_content.id,
+        "id": doc! { "$ne": jwt.session_id }
+    })
+    .await?;
+    let user = user::get_collection()
+        .find_one(doc! {
+            "id": &jwt.jwt_content.id
         })
-        .await?;
+        .await?
+        .ok_or(Error::DatabaseError)?;
+    notify(&req, &user, SecurityNotice::SessionsRevoked).await;
     Ok(web::Json(LogoutAllResponse {}))
 }
@@ -2,7 +2,11 @@ use actix_web::{web, Responder};
 use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
 
-use crate::{authenticate::Authenticate, database::session::get_collection, errors::Result};
+use crate::{
+    authenticate::Authenticate,
+    database::session,
+    errors::{Error, Result},
+};
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -12,10 +16,14 @@ pub async fn handle(
     jwt: web::ReqData<Result<Authenticate>>,
     logout_other: web::Path<String>,
 ) -> Result<impl Responder> {
-    jwt.into_inner()?;
-    let sessions = get_collection();
-    sessions
-        .delete_one(doc! { "id": &logout_other.into_inner() })
-        .await?;
+    let jwt = jwt.into_inner()?;
+    let revoked = session::revoke(doc! {
+        "id": &logout_other.into_inner(),
+        "user_id": &jwt.jwt_content.id
+    })
+    .await?;
+    if revoked == 0 {
+        return Err(Error::SessionNotFound);
+    }
     Ok(web::Json(LogoutOtherResponse {}))
 }
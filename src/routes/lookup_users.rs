@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use actix_web::{web, Responder};
+use futures_util::TryStreamExt;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::MachineAuthenticate,
+    constants::USER_LOOKUP_MAX,
+    database::{machine_client::MachineScope, profile, user},
+    errors::{Error, Result},
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LookupUsers {
+    ids: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntry {
+    id: String,
+    username: String,
+    display_name: String,
+    description: String,
+    website: String,
+    avatar: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LookupUsersResponse {
+    // in the order requested; unknown IDs are left out
+    users: Vec<UserEntry>,
+}
+
+pub async fn handle(
+    jwt: web::ReqData<Result<MachineAuthenticate>>,
+    lookup: web::Json<LookupUsers>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    jwt.require(MachineScope::UsersRead)?;
+    let ids = lookup.into_inner().ids;
+    if ids.len() > USER_LOOKUP_MAX {
+        return Err(Error::TooManyUsers);
+    }
+    let mut users = user::get_collection()
+        .find(doc! {
+            "id": { "$in": ids.clone() }
+        })
+        .await?
+        .map_ok(|user| (user.id.clone(), user))
+        .try_collect::<HashMap<_, _>>()
+        .await?;
+    let mut profiles = profile::get_collection()
+        .find(doc! {
+            "id": { "$in": ids.clone() }
+        })
+        .await?
+        .map_ok(|profile| (profile.id.clone(), profile))
+        .try_collect::<HashMap<_, _>>()
+        .await?;
+    let users = ids
+        .iter()
+        .filter_map(|id| {
+            let user = users.remove(id)?;
+            let profile = profiles.remove(id)?;
+            Some(UserEntry {
+                id: user.id,
+                username: user.username,
+                display_name: profile.display_name,
+                description: profile.description,
+                website: profile.website,
+                avatar: profile.avatar,
+            })
+        })
+        .collect();
+    Ok(web::Json(LookupUsersResponse { users }))
+}
@@ -1,19 +1,24 @@
-use actix_web::{web, Responder};
-use dashmap::DashMap;
-use lazy_static::lazy_static;
+use actix_web::{web, HttpRequest, Responder};
 use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use totp_rs::{Secret, TOTP};
 
 use crate::{
     authenticate::Authenticate,
+    constants::CONTINUE_TIMEOUT,
     database::{
+        audit_event::{self, AuditEventKind},
         code,
-        user::{self, User},
+        event::{self, EventKind},
+        user,
     },
+    encryption,
     environment::SERVICE_NAME,
     errors::{Error, Result},
-    utilities::{generate_codes, get_time_secs, random_number, validate_escalation},
+    flows::Flow,
+    notifications::{notify, SecurityNotice},
+    utilities::{generate_codes, random_number, validate_escalation},
 };
 
 #[derive(Deserialize, Serialize)]
@@ -42,17 +47,31 @@ pub enum MfaResponse {
     EnableVerify {},
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PendingMfaSetup {
-    pub totp: TOTP,
+    // base32 encoded, then sealed with encryption::seal
     pub secret: String,
-    pub time: u64,
-    pub user: User,
+    // hashed with code::hash_code
+    pub codes: Vec<String>,
+    pub user_id: String,
 }
-lazy_static! {
-    pub static ref PENDING_MFA_SETUPS: DashMap<String, PendingMfaSetup> = DashMap::new();
+pub static PENDING_MFA_SETUPS: Flow<PendingMfaSetup> = Flow::new("mfa_setup", CONTINUE_TIMEOUT);
+
+fn create_totp(secret: Vec<u8>, account_name: String) -> TOTP {
+    TOTP::new(
+        totp_rs::Algorithm::SHA256,
+        8,
+        1,
+        30,
+        secret,
+        Some(SERVICE_NAME.to_string()),
+        account_name,
+    )
+    .expect("Unexpected error: failed to initiate TOTP")
 }
 
 pub async fn handle(
+    req: HttpRequest,
     jwt: web::ReqData<Result<Authenticate>>,
     mfa: web::Json<Mfa>,
 ) -> Result<impl Responder> {
@@ -60,7 +79,7 @@ pub async fn handle(
     let mfa = mfa.into_inner();
     match mfa {
         Mfa::Toggle { escalation_token } => {
-            validate_escalation(escalation_token, jwt.jwt).await?;
+            validate_escalation(escalation_token, jwt.session_id.clone()).await?;
             let user = user::get_collection()
                 .find_one(doc! {"id": jwt.jwt_content.id})
                 .await?
@@ -84,32 +103,38 @@ pub async fn handle(
                         "user_id": user.id.clone()
                     })
                     .await?;
+                audit_event::record(
+                    &req,
+                    AuditEventKind::MfaDisabled,
+                    Some(&user.id),
+                    &user.id,
+                    Some(&jwt.session_id),
+                    None,
+                )
+                .await;
+                event::publish(
+                    EventKind::MfaChanged,
+                    &user.id,
+                    Some(json!({ "enabled": false })),
+                )
+                .await;
+                notify(&req, &user, SecurityNotice::MfaDisabled).await;
                 Ok(web::Json(MfaResponse::Disable {}))
             } else {
                 let secret = random_number(160);
-                let totp = TOTP::new(
-                    totp_rs::Algorithm::SHA256,
-                    8,
-                    1,
-                    30,
-                    secret.clone(),
-                    Some(SERVICE_NAME.to_string()),
-                    user.username.clone(),
-                )
-                .expect("Unexpected error: failed to initiate TOTP");
+                let totp = create_totp(secret.clone(), user.username.clone());
                 let qr = totp
                     .get_qr_base64()
                     .expect("Unexpected error: failed to generate QR code");
                 let continue_token = ulid::Ulid::new().to_string();
                 let code = Secret::Raw(secret.to_vec()).to_encoded().to_string();
+                let codes = generate_codes();
                 let session = PendingMfaSetup {
-                    time: get_time_secs(),
-                    user,
-                    secret: code.clone(),
-                    totp,
+                    secret: encryption::seal(&code).await?,
+                    codes: code::hash_codes(&codes).await,
+                    user_id: user.id,
                 };
-                PENDING_MFA_SETUPS.insert(continue_token.clone(), session);
-                let codes = generate_codes();
+                PENDING_MFA_SETUPS.insert(&continue_token, &session).await?;
                 Ok(web::Json(MfaResponse::Enable {
                     continue_token,
                     qr,
@@ -122,15 +147,19 @@ pub async fn handle(
             code,
             continue_token,
         } => {
-            let enable_session = PENDING_MFA_SETUPS.get(&continue_token);
+            let enable_session = PENDING_MFA_SETUPS.get(&continue_token).await?;
             if let Some(enable_session) = enable_session {
-                if get_time_secs() - enable_session.time > 3600 {
-                    drop(enable_session);
-                    PENDING_MFA_SETUPS.remove(&continue_token);
+                if enable_session.user_id != jwt.jwt_content.id {
                     return Err(Error::SessionExpired);
                 }
-                let current = enable_session
-                    .totp
+                let user = user::get_collection()
+                    .find_one(doc! {"id": &enable_session.user_id})
+                    .await?
+                    .ok_or(Error::DatabaseError)?;
+                let secret = Secret::Encoded(encryption::open(&enable_session.secret).await?)
+                    .to_bytes()
+                    .map_err(|_| Error::DatabaseError)?;
+                let current = create_totp(secret, user.username.clone())
                     .generate_current()
                     .expect("Unexpected error: failed to generate code");
                 if current != code {
@@ -140,18 +169,33 @@ pub async fn handle(
                 collection
                     .update_one(
                         doc! {
-                            "id": enable_session.user.id.clone(),
+                            "id": user.id.clone(),
                         },
                         doc! {
                             "$set": {
                                 "mfa_enabled": true,
-                                "mfa_secret": enable_session.secret.clone()
+                                "mfa_secret": &enable_session.secret
                             }
                         },
                     )
                     .await?;
-                drop(enable_session);
-                PENDING_MFA_SETUPS.remove(&continue_token);
+                code::replace_codes(&user.id, enable_session.codes).await?;
+                audit_event::record(
+                    &req,
+                    AuditEventKind::MfaEnabled,
+                    Some(&user.id),
+                    &user.id,
+                    Some(&jwt.session_id),
+                    None,
+                )
+                .await;
+                event::publish(
+                    EventKind::MfaChanged,
+                    &user.id,
+                    Some(json!({ "enabled": true })),
+                )
+                .await;
+                PENDING_MFA_SETUPS.complete(&continue_token).await?;
                 Ok(web::Json(MfaResponse::EnableVerify {}))
             } else {
                 Err(Error::SessionExpired)
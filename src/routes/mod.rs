@@ -1,21 +1,60 @@
 pub mod account_settings;
+pub mod admin_audit_events;
+pub mod admin_create_machine_client;
+pub mod admin_create_webhook;
+pub mod admin_delete_machine_client;
+pub mod admin_delete_webhook;
+pub mod admin_email_outbox;
+pub mod admin_logout;
+pub mod admin_machine_clients;
+pub mod admin_passkeys;
+pub mod admin_reset_mfa;
+pub mod admin_reset_password;
+pub mod admin_search_users;
+pub mod admin_sessions;
+pub mod admin_status;
+pub mod admin_webhook_deliveries;
+pub mod admin_webhooks;
+pub mod authorize;
+pub mod captcha;
+pub mod create_client;
 pub mod current_user;
+pub mod default_avatar;
 pub mod delete;
+pub mod delete_avatar;
 pub mod delete_passkey;
+pub mod export;
+pub mod flow_metrics;
 pub mod forgot;
+pub mod get_codes;
+pub mod get_export;
 pub mod get_passkey;
 pub mod ip;
+pub mod jwks;
+pub mod lockdown;
 pub mod login;
 pub mod login_passkey;
 pub mod logout;
 pub mod logout_all;
 pub mod logout_other;
+pub mod lookup_users;
 pub mod mfa;
+pub mod openid_configuration;
 pub mod profile_settings;
+pub mod refresh;
+pub mod regenerate_codes;
 pub mod register;
 pub mod register_passkey;
+pub mod restore;
+pub mod security_log;
 pub mod service;
 pub mod session;
+pub mod token;
+pub mod unlock;
+pub mod update_avatar;
+pub mod update_email;
 pub mod update_password;
 pub mod user;
+pub mod userinfo;
 pub mod validate;
+pub mod verify_token;
@@ -0,0 +1,59 @@
+use actix_web::{web, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::{environment::PUBLIC_ROOT, oidc::SUPPORTED_SCOPES};
+
+#[derive(Deserialize, Serialize)]
+pub struct OpenIdConfigurationResponse {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+    jwks_uri: String,
+    scopes_supported: Vec<String>,
+    response_types_supported: Vec<String>,
+    grant_types_supported: Vec<String>,
+    subject_types_supported: Vec<String>,
+    id_token_signing_alg_values_supported: Vec<String>,
+    token_endpoint_auth_methods_supported: Vec<String>,
+    code_challenge_methods_supported: Vec<String>,
+    claims_supported: Vec<String>,
+}
+
+pub async fn handle() -> impl Responder {
+    let root = &*PUBLIC_ROOT;
+    web::Json(OpenIdConfigurationResponse {
+        issuer: root.to_string(),
+        // consent is handled by the client bundle, which then calls /api/oauth/authorize
+        authorization_endpoint: format!("{}/authorize", root),
+        token_endpoint: format!("{}/api/oauth/token", root),
+        userinfo_endpoint: format!("{}/api/oauth/userinfo", root),
+        jwks_uri: format!("{}/.well-known/jwks.json", root),
+        scopes_supported: SUPPORTED_SCOPES.iter().map(|s| s.to_string()).collect(),
+        response_types_supported: vec!["code".to_string()],
+        grant_types_supported: vec![
+            "authorization_code".to_string(),
+            "client_credentials".to_string(),
+        ],
+        subject_types_supported: vec!["public".to_string()],
+        id_token_signing_alg_values_supported: vec!["ES256".to_string(), "EdDSA".to_string()],
+        token_endpoint_auth_methods_supported: vec![
+            "client_secret_basic".to_string(),
+            "client_secret_post".to_string(),
+            "private_key_jwt".to_string(),
+            "none".to_string(),
+        ],
+        code_challenge_methods_supported: vec!["S256".to_string()],
+        claims_supported: [
+            "sub",
+            "email",
+            "email_verified",
+            "preferred_username",
+            "name",
+            "website",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect(),
+    })
+}
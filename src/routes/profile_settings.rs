@@ -1,13 +1,19 @@
 use actix_web::{web, Responder};
 use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
 use crate::{
     authenticate::Authenticate,
-    database::{files::File, profile::get_collection},
+    database::{
+        event::{self, EventKind},
+        profile::get_collection,
+    },
     errors::{Error, Result},
 };
 
+use super::update_avatar::{detach_avatar, get_avatar_file};
+
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ProfileSettings {
@@ -34,41 +40,66 @@ pub async fn handle(
         .await?
         .ok_or(Error::DatabaseError)?;
     let mut update_query = doc! {};
+    // named as in GET /user/{id}, for the user.updated event
+    let mut fields = Vec::new();
     if let Some(display_name) = profile_settings.display_name {
         if display_name.trim().len() > 64 {
             return Err(Error::DisplayNameTooLong);
         }
         update_query.insert("display_name", display_name.trim());
+        fields.push("displayName");
     }
     if let Some(description) = profile_settings.description {
         if description.trim().len() > 2048 {
             return Err(Error::DescriptionTooLong);
         }
         update_query.insert("description", description.trim());
+        fields.push("description");
     }
     if let Some(website) = profile_settings.website {
         if website.trim().len() > 256 {
             return Err(Error::WebsiteTooLong);
         }
         update_query.insert("website", website.trim());
+        fields.push("website");
     }
+    let mut previous_avatar = None;
+    let mut attached_file = None;
     if let Some(avatar) = profile_settings.avatar {
-        if avatar != "default" {
-            let file = File::get(&avatar).await?;
-            file.attach().await?;
-        }
-        if let Ok(file) = File::get(&profile.id).await {
-            file.detach().await?;
+        if profile.avatar.as_ref() != Some(&avatar) {
+            if avatar != "default" {
+                let file = get_avatar_file(&avatar).await?;
+                file.attach().await?;
+                attached_file = Some(file);
+            }
+            previous_avatar = profile.avatar;
+            update_query.insert("avatar", avatar);
+            fields.push("avatar");
         }
-        update_query.insert("avatar", avatar);
     }
-    collection
+    let result = collection
         .update_one(
-            doc! {"id": jwt.jwt_content.id},
+            doc! {"id": &jwt.jwt_content.id},
             doc! {
                 "$set": update_query
             },
         )
-        .await?;
+        .await;
+    // don't leave the new avatar attached to a profile that doesn't use it
+    if let Err(error) = result {
+        if let Some(file) = attached_file {
+            file.detach().await.ok();
+        }
+        return Err(error.into());
+    }
+    detach_avatar(previous_avatar).await?;
+    if !fields.is_empty() {
+        event::publish(
+            EventKind::UserUpdated,
+            &jwt.jwt_content.id,
+            Some(json!({ "fields": fields })),
+        )
+        .await;
+    }
     Ok(web::Json(ProfileSettingsResponse {}))
 }
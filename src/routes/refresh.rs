@@ -0,0 +1,103 @@
+use actix_web::{web, HttpRequest, Responder};
+use log::warn;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::create_access_token,
+    constants::REFRESH_TOKEN_HISTORY,
+    database::{
+        session::{self, get_ip, get_lifetime},
+        user,
+    },
+    errors::{Error, Result},
+    geoip,
+    utilities::{generate_continue_token_long, get_time_secs, hash_secret},
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Refresh {
+    refresh_token: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshResponse {
+    token: String,
+    refresh_token: String,
+}
+
+pub async fn handle(req: HttpRequest, refresh: web::Json<Refresh>) -> Result<impl Responder> {
+    let refresh = refresh.into_inner();
+    let hashed = hash_secret(&refresh.refresh_token);
+    let now = get_time_secs();
+    let collection = session::get_collection();
+    let session = collection
+        .find_one(doc! {
+            "refresh_token": &hashed
+        })
+        .await?;
+    let Some(session) = session else {
+        // a refresh token that was already rotated out has been copied somewhere,
+        // so neither copy of the session can be trusted
+        let reused = collection
+            .find_one(doc! {
+                "previous_refresh_tokens": &hashed
+            })
+            .await?;
+        if let Some(reused) = reused {
+            warn!(
+                "Refresh token reused for session {}, revoking it",
+                reused.id
+            );
+            session::revoke(doc! { "id": &reused.id }).await?;
+        }
+        return Err(Error::InvalidToken);
+    };
+    if session.expires_at <= now {
+        collection.delete_one(doc! { "id": &session.id }).await?;
+        return Err(Error::SessionExpired);
+    }
+    user::get_collection()
+        .find_one(doc! {
+            "id": &session.user_id
+        })
+        .await?
+        .ok_or(Error::InvalidToken)?
+        .ensure_active()?;
+    let refresh_token = generate_continue_token_long();
+    let ip = get_ip(&req);
+    // matching on the old token means only one of several concurrent refreshes succeeds
+    let result = collection
+        .update_one(
+            doc! {
+                "id": &session.id,
+                "refresh_token": &hashed
+            },
+            doc! {
+                "$set": {
+                    "refresh_token": hash_secret(&refresh_token),
+                    "expires_at": (now + get_lifetime(session.persistent)) as i64,
+                    "last_used_at": now as i64,
+                    "location": ip.as_deref().and_then(geoip::lookup),
+                    "ip": ip,
+                },
+                "$push": {
+                    "previous_refresh_tokens": {
+                        "$each": [&hashed],
+                        "$slice": -REFRESH_TOKEN_HISTORY
+                    }
+                }
+            },
+        )
+        .await?;
+    if result.modified_count == 0 {
+        return Err(Error::InvalidToken);
+    }
+    let token = create_access_token(&session.user_id, &session.id).await?;
+    Ok(web::Json(RefreshResponse {
+        token,
+        refresh_token,
+    }))
+}
@@ -0,0 +1,46 @@
+use actix_web::{web, Responder};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::{code, user},
+    errors::{Error, Result},
+    utilities::{generate_codes, validate_escalation},
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegenerateCodes {
+    escalation_token: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegenerateCodesResponse {
+    codes: Vec<String>,
+}
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    regenerate_codes: web::Json<RegenerateCodes>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let user_id = validate_escalation(
+        regenerate_codes.into_inner().escalation_token,
+        jwt.session_id,
+    )
+    .await?;
+    let user = user::get_collection()
+        .find_one(doc! {
+            "id": &user_id
+        })
+        .await?
+        .ok_or(Error::DatabaseError)?;
+    if !user.mfa_enabled {
+        return Err(Error::MfaNotEnabled);
+    }
+    let codes = generate_codes();
+    code::replace_codes(&user_id, code::hash_codes(&codes).await).await?;
+    Ok(web::Json(RegenerateCodesResponse { codes }))
+}
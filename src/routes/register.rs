@@ -1,24 +1,29 @@
-use actix_web::{web, Responder};
+use actix_web::{web, HttpRequest, Responder};
 use async_std::task;
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
-use dashmap::DashMap;
-use jsonwebtoken::{encode, EncodingKey, Header};
-use lazy_static::lazy_static;
 use mongodb::bson::doc;
 use opaque_ke::{RegistrationRequest, RegistrationUpload};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
 use crate::{
-    authenticate::UserJwt,
-    constants::{LONG_SESSION, SHORT_SESSION},
-    database::{profile::UserProfile, session::Session, user::User},
-    environment::{JWT_SECRET, SMTP_ENABLED},
+    authenticate::create_session,
+    captcha::validate_captcha,
+    constants::SHORT_CONTINUE_TIMEOUT,
+    database::{
+        event::{self, EventKind},
+        profile::UserProfile,
+        start_transaction,
+        user::{AccountStatus, User},
+    },
+    environment::EMAIL_ENABLED,
     errors::{Error, Result},
+    flows::Flow,
     opaque::{begin_registration, finish_registration},
+    templates::{get_accept_language, resolve_locale},
     utilities::{
-        generate_codes, generate_continue_token_long, get_time_millis, get_time_secs,
-        send_in_use_email, send_verify_email, validate_captcha, EMAIL_RE, USERNAME_RE,
+        generate_codes, generate_continue_token_long, send_in_use_email, send_verify_email,
+        EMAIL_RE, USERNAME_RE,
     },
 };
 
@@ -66,30 +71,32 @@ pub enum RegisterResponse {
         message: String,
         // opaque data
     },
+    #[serde(rename_all = "camelCase")]
     Register {
         token: String,
+        refresh_token: String,
         // opaque data 2
     },
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PendingRegister {
-    pub time: u64,
     pub email: String,
 }
 
-lazy_static! {
-    pub static ref PENDING_REGISTERS1: DashMap<String, PendingRegister> = DashMap::new();
-    pub static ref PENDING_REGISTERS2: DashMap<String, PendingRegister> = DashMap::new();
-}
+pub static PENDING_REGISTERS1: Flow<PendingRegister> =
+    Flow::new("register_email", SHORT_CONTINUE_TIMEOUT);
+pub static PENDING_REGISTERS2: Flow<PendingRegister> =
+    Flow::new("register", SHORT_CONTINUE_TIMEOUT);
 
-pub async fn handle(register: web::Json<Register>) -> Result<impl Responder> {
+pub async fn handle(req: HttpRequest, register: web::Json<Register>) -> Result<impl Responder> {
     let register = register.into_inner();
     match register {
         Register::VerifyEmail {
             email,
             captcha_token,
         } => {
-            validate_captcha(captcha_token).await?;
+            validate_captcha(&req, &captcha_token).await?;
             if !EMAIL_RE.is_match(email.trim()) {
                 return Err(Error::InvalidEmail);
             }
@@ -99,19 +106,22 @@ pub async fn handle(register: web::Json<Register>) -> Result<impl Responder> {
                     "email": email.clone()
                 })
                 .await?;
-            if *SMTP_ENABLED {
-                if user.is_some() {
-                    task::spawn(send_in_use_email(email.clone()));
+            if *EMAIL_ENABLED {
+                if let Some(user) = &user {
+                    task::spawn(send_in_use_email(
+                        email.clone(),
+                        resolve_locale(user.locale.as_deref(), Some(&req)),
+                    ));
                 } else {
                     let token = generate_codes().first().unwrap().to_string();
-                    task::spawn(send_verify_email(email.clone(), token.clone()));
-                    PENDING_REGISTERS1.insert(
-                        token,
-                        PendingRegister {
-                            time: get_time_secs(),
-                            email,
-                        },
-                    );
+                    task::spawn(send_verify_email(
+                        email.clone(),
+                        resolve_locale(None, Some(&req)),
+                        token.clone(),
+                    ));
+                    PENDING_REGISTERS1
+                        .insert(&token, &PendingRegister { email })
+                        .await?;
                 }
                 Ok(web::Json(RegisterResponse::VerifyEmail {
                     email_enabled: true,
@@ -122,13 +132,9 @@ pub async fn handle(register: web::Json<Register>) -> Result<impl Responder> {
                     return Err(Error::UserExists);
                 }
                 let token = generate_continue_token_long();
-                PENDING_REGISTERS1.insert(
-                    token.clone(),
-                    PendingRegister {
-                        time: get_time_secs(),
-                        email,
-                    },
-                );
+                PENDING_REGISTERS1
+                    .insert(&token, &PendingRegister { email })
+                    .await?;
                 Ok(web::Json(RegisterResponse::VerifyEmail {
                     email_enabled: false,
                     email_token: Some(token),
@@ -139,23 +145,18 @@ pub async fn handle(register: web::Json<Register>) -> Result<impl Responder> {
             email_token: token,
             message,
         } => {
-            if let Some(session) = PENDING_REGISTERS1.get(&token) {
-                let time = get_time_secs();
-                if time - session.time > 600 {
-                    drop(session);
-                    PENDING_REGISTERS1.remove(&token);
-                    return Err(Error::SessionExpired);
-                }
-                let email = session.email.clone();
+            if let Some(session) = PENDING_REGISTERS1.get(&token).await? {
+                let email = session.email;
                 let result = begin_registration(
                     email.clone(),
                     RegistrationRequest::deserialize(&BASE64.decode(message)?)?,
                 )
                 .await?;
-                drop(session);
-                PENDING_REGISTERS1.remove(&token);
+                PENDING_REGISTERS1.complete(&token).await?;
                 let continue_token = generate_continue_token_long();
-                PENDING_REGISTERS2.insert(continue_token.clone(), PendingRegister { time, email });
+                PENDING_REGISTERS2
+                    .insert(&continue_token, &PendingRegister { email })
+                    .await?;
                 return Ok(web::Json(RegisterResponse::BeginRegistration {
                     continue_token,
                     message: BASE64.encode(result),
@@ -169,13 +170,9 @@ pub async fn handle(register: web::Json<Register>) -> Result<impl Responder> {
             persist,
             display_name,
             message,
-            continue_token: token,
+            continue_token,
         } => {
-            if let Some(session) = PENDING_REGISTERS2.get(&token) {
-                if get_time_secs() - session.time > 600 {
-                    PENDING_REGISTERS2.remove(&token);
-                    return Err(Error::SessionExpired);
-                }
+            if let Some(session) = PENDING_REGISTERS2.get(&continue_token).await? {
                 if display_name.trim().len() > 64 {
                     return Err(Error::DisplayNameTooLong);
                 }
@@ -203,6 +200,9 @@ pub async fn handle(register: web::Json<Register>) -> Result<impl Responder> {
                     email: session.email.trim().to_string(),
                     password_data,
                     platform_administrator: false,
+                    status: AccountStatus::Active,
+                    locale: get_accept_language(&req).into_iter().next(),
+                    deletion: None,
                 };
                 let profile_document = UserProfile {
                     id: user_id.clone(),
@@ -211,39 +211,38 @@ pub async fn handle(register: web::Json<Register>) -> Result<impl Responder> {
                     website: String::new(),
                     avatar: None,
                 };
-                let user_collection = crate::database::user::get_collection();
-                user_collection.insert_one(user_document).await?;
-                let profile_collection = crate::database::profile::get_collection();
-                profile_collection.insert_one(profile_document).await?;
-                let persist = persist.unwrap_or(false);
-                let millis = get_time_millis();
-                let expires_at = if persist {
-                    millis + LONG_SESSION
-                } else {
-                    millis + SHORT_SESSION
-                };
-                let jwt_object = UserJwt {
-                    id: user_id.clone(),
-                    issued_at: millis,
-                    expires_at,
-                };
-                let token = encode(
-                    &Header::default(),
-                    &jwt_object,
-                    &EncodingKey::from_secret(JWT_SECRET.as_ref()),
-                )
-                .expect("Unexpected error: failed to encode token");
-                let sid = ulid::Ulid::new().to_string();
-                let session = Session {
-                    id: sid,
-                    token: token.clone(),
-                    friendly_name: friendly_name.unwrap_or("Unknown".to_owned()),
+                // the user, profile and user.created event are created together or not at all
+                let mut transaction = start_transaction().await?;
+                let result: Result<()> = async {
+                    crate::database::user::get_collection()
+                        .insert_one(user_document)
+                        .session(&mut transaction)
+                        .await?;
+                    crate::database::profile::get_collection()
+                        .insert_one(profile_document)
+                        .session(&mut transaction)
+                        .await?;
+                    event::emit(&mut transaction, EventKind::UserCreated, &user_id, None).await?;
+                    Ok(())
+                }
+                .await;
+                if let Err(e) = result {
+                    transaction.abort_transaction().await.ok();
+                    return Err(e);
+                }
+                transaction.commit_transaction().await?;
+                let tokens = create_session(
                     user_id,
-                };
-                let sessions = crate::database::session::get_collection();
-                sessions.insert_one(session).await?;
-                PENDING_REGISTERS2.remove(&token);
-                return Ok(web::Json(RegisterResponse::Register { token }));
+                    friendly_name.unwrap_or("Unknown".to_owned()),
+                    persist.unwrap_or(false),
+                    &req,
+                )
+                .await?;
+                PENDING_REGISTERS2.complete(&continue_token).await?;
+                return Ok(web::Json(RegisterResponse::Register {
+                    token: tokens.token,
+                    refresh_token: tokens.refresh_token,
+                }));
             }
             Err(Error::SessionExpired)
         }
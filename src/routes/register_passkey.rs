@@ -1,10 +1,8 @@
 use actix_web::{
     web::{self, Data},
-    Responder,
+    HttpRequest, Responder,
 };
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
-use dashmap::DashMap;
-use lazy_static::lazy_static;
 use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
@@ -15,12 +13,16 @@ use webauthn_rs::{
 
 use crate::{
     authenticate::Authenticate,
+    constants::CONTINUE_TIMEOUT,
     database::{
+        audit_event::{self, AuditEventKind},
         passkey::{self, Passkey},
-        user::User,
+        user,
     },
     errors::{Error, Result},
-    utilities::{generate_continue_token_long, get_time_secs, validate_escalation},
+    flows::Flow,
+    notifications::{notify, SecurityNotice},
+    utilities::{generate_continue_token_long, validate_escalation},
 };
 
 #[derive(Deserialize, Serialize)]
@@ -47,27 +49,28 @@ pub enum RegisterResponse {
     FinishRegister {},
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PendingRegister {
-    pub time: u64,
-    pub user: User,
+    pub user_id: String,
     pub email: String,
     pub data: PasskeyRegistration,
 }
 
-lazy_static! {
-    pub static ref PENDING_REGISTERS: DashMap<String, PendingRegister> = DashMap::new();
-}
+pub static PENDING_REGISTERS: Flow<PendingRegister> =
+    Flow::new("register_passkey", CONTINUE_TIMEOUT);
 
 pub async fn handle(
+    req: HttpRequest,
     jwt: web::ReqData<Result<Authenticate>>,
     register: web::Json<Register>,
     webauthn: Data<Webauthn>,
 ) -> Result<impl Responder> {
+    let jwt = jwt.into_inner();
     let register = register.into_inner();
     match register {
         Register::BeginRegister { escalation_token } => {
-            let user_id = validate_escalation(escalation_token, jwt.into_inner()?.jwt).await?;
-            let user = crate::database::user::get_collection()
+            let user_id = validate_escalation(escalation_token, jwt?.session_id).await?;
+            let user = user::get_collection()
                 .find_one(doc! {
                     "id": user_id.clone()
                 })
@@ -80,12 +83,13 @@ pub async fn handle(
                 webauthn.start_passkey_registration(uuid, &user.username, &user.username, None)?;
             let continue_token = generate_continue_token_long();
             let pending_register = PendingRegister {
-                time: get_time_secs(),
                 email: user.username.clone(),
-                user,
+                user_id: user.id,
                 data: reg_state,
             };
-            PENDING_REGISTERS.insert(continue_token.clone(), pending_register);
+            PENDING_REGISTERS
+                .insert(&continue_token, &pending_register)
+                .await?;
             Ok(web::Json(RegisterResponse::BeginRegister {
                 continue_token,
                 message: ccr,
@@ -96,30 +100,48 @@ pub async fn handle(
             continue_token,
             friendly_name,
         } => {
-            let pending_register = PENDING_REGISTERS.get(&continue_token);
+            let pending_register = PENDING_REGISTERS.get(&continue_token).await?;
             let Some(pending_register) = pending_register else {
                 return Err(Error::SessionExpired);
             };
-            if get_time_secs() - pending_register.time > 3600 {
-                drop(pending_register);
-                PENDING_REGISTERS.remove(&continue_token);
-                return Err(Error::SessionExpired);
-            }
             let auth_result =
                 webauthn.finish_passkey_registration(&message, &pending_register.data)?;
             let credential_id = auth_result.cred_id().as_ref().to_vec();
-            let user = pending_register.user.clone();
+            let user = user::get_collection()
+                .find_one(doc! {
+                    "id": &pending_register.user_id
+                })
+                .await?
+                .ok_or(Error::SessionExpired)?;
+            let passkey_id = Ulid::new().to_string();
+            let friendly_name = friendly_name.unwrap_or("Passkey".to_string());
             passkey::get_collection()
                 .insert_one(Passkey {
-                    id: Ulid::new().to_string(),
+                    id: passkey_id.clone(),
                     credential: auth_result,
                     credential_id: BASE64.encode(credential_id),
                     user_id: user.id.clone(),
-                    friendly_name: friendly_name.unwrap_or("Passkey".to_string()),
+                    friendly_name: friendly_name.clone(),
                 })
                 .await?;
-            drop(pending_register);
-            PENDING_REGISTERS.remove(&continue_token);
+            audit_event::record(
+                &req,
+                AuditEventKind::PasskeyRegistered,
+                Some(&user.id),
+                &user.id,
+                jwt.ok().map(|jwt| jwt.session_id).as_deref(),
+                Some(passkey_id),
+            )
+            .await;
+            notify(
+                &req,
+                &user,
+                SecurityNotice::PasskeyAdded {
+                    name: friendly_name,
+                },
+            )
+            .await;
+            PENDING_REGISTERS.complete(&continue_token).await?;
             Ok(web::Json(RegisterResponse::FinishRegister {}))
         }
     }
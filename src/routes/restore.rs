@@ -0,0 +1,27 @@
+use actix_web::{web, HttpRequest, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    deletion,
+    errors::{Error, Result},
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Restore {
+    token: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreResponse {}
+
+// reached from the cancel link in the email sent when deletion is requested
+pub async fn handle(req: HttpRequest, restore: web::Json<Restore>) -> Result<impl Responder> {
+    let token = restore.into_inner().token;
+    let Some(user) = deletion::find_by_cancel_token(&token).await? else {
+        return Err(Error::SessionExpired);
+    };
+    deletion::cancel(&req, &user).await?;
+    Ok(web::Json(RestoreResponse {}))
+}
@@ -0,0 +1,82 @@
+use actix_web::{web, Responder};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    constants::{AUDIT_PAGE_MAX, AUDIT_PAGE_SIZE},
+    database::{
+        audit_event::{self, AuditEvent, AuditEventKind},
+        session::UserAgent,
+    },
+    errors::Result,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecurityLog {
+    before: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEventEntry {
+    id: String,
+    event: AuditEventKind,
+    actor_id: Option<String>,
+    target_id: String,
+    session_id: Option<String>,
+    ip: Option<String>,
+    user_agent: Option<UserAgent>,
+    detail: Option<String>,
+    created_at: u64,
+}
+
+impl From<AuditEvent> for AuditEventEntry {
+    fn from(event: AuditEvent) -> Self {
+        AuditEventEntry {
+            id: event.id,
+            event: event.event,
+            actor_id: event.actor_id,
+            target_id: event.target_id,
+            session_id: event.session_id,
+            ip: event.ip,
+            user_agent: event.user_agent,
+            detail: event.detail,
+            created_at: event.created_at,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEventPage {
+    pub events: Vec<AuditEventEntry>,
+    // pass as `before` to fetch the next (older) page
+    pub next_cursor: Option<String>,
+}
+
+pub fn page_size(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(AUDIT_PAGE_SIZE).clamp(1, AUDIT_PAGE_MAX)
+}
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    security_log: web::Query<SecurityLog>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let security_log = security_log.into_inner();
+    let (events, next_cursor) = audit_event::query(
+        doc! {
+            "target_id": &jwt.jwt_content.id
+        },
+        security_log.before,
+        page_size(security_log.limit),
+    )
+    .await?;
+    Ok(web::Json(AuditEventPage {
+        events: events.into_iter().map(AuditEventEntry::from).collect(),
+        next_cursor,
+    }))
+}
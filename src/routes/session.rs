@@ -4,7 +4,7 @@ use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
 
 use crate::authenticate::Authenticate;
-use crate::database::session::{self, Session};
+use crate::database::session::{self, Session, UserAgent};
 use crate::errors::Result;
 
 #[derive(Deserialize, Serialize)]
@@ -12,6 +12,12 @@ use crate::errors::Result;
 pub struct ClientSession {
     id: String,
     friendly_name: String,
+    created_at: u64,
+    last_used_at: u64,
+    ip: Option<String>,
+    user_agent: Option<UserAgent>,
+    location: Option<String>,
+    current: bool,
 }
 
 pub async fn handle(jwt: web::ReqData<Result<Authenticate>>) -> Result<impl Responder> {
@@ -30,8 +36,14 @@ pub async fn handle(jwt: web::ReqData<Result<Authenticate>>) -> Result<impl Resp
     let result = result
         .into_iter()
         .map(|session| ClientSession {
+            current: session.id == jwt.session_id,
             id: session.id,
             friendly_name: session.friendly_name,
+            created_at: session.created_at,
+            last_used_at: session.last_used_at,
+            ip: session.ip,
+            user_agent: session.user_agent,
+            location: session.location,
         })
         .collect::<Vec<ClientSession>>();
 
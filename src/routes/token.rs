@@ -0,0 +1,174 @@
+use actix_web::{web, HttpRequest, Responder};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::{MACHINE_TOKEN_LIFETIME, OAUTH_TOKEN_LIFETIME},
+    database::client::{self, Client},
+    environment::PUBLIC_ROOT,
+    errors::{Error, Result},
+    machine::{self, CLIENT_ASSERTION_TYPE},
+    oidc::{get_user_claims, verify_pkce, AccessTokenClaims, IdTokenClaims},
+    signing,
+    utilities::{get_time_secs, hash_secret},
+};
+
+use super::authorize::PENDING_AUTHORIZATIONS;
+
+#[derive(Deserialize, Serialize)]
+pub struct Token {
+    grant_type: String,
+    code: Option<String>,
+    redirect_uri: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    code_verifier: Option<String>,
+    scope: Option<String>,
+    client_assertion_type: Option<String>,
+    client_assertion: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct TokenResponse {
+    access_token: String,
+    token_type: String,
+    expires_in: u64,
+    // only for authorization_code, as machine tokens don't stand for a user
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id_token: Option<String>,
+    scope: String,
+}
+
+// client_secret_basic takes precedence over client_secret_post
+fn get_client_credentials(req: &HttpRequest, token: &Token) -> Option<(String, Option<String>)> {
+    let basic = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Basic "))
+        .and_then(|h| STANDARD.decode(h).ok())
+        .and_then(|h| String::from_utf8(h).ok());
+    if let Some(basic) = basic {
+        let (id, secret) = basic.split_once(':')?;
+        return Some((id.to_string(), Some(secret.to_string())));
+    }
+    Some((token.client_id.clone()?, token.client_secret.clone()))
+}
+
+async fn authenticate_client(id: String, secret: Option<String>) -> Result<Client> {
+    let client = client::get_collection()
+        .find_one(doc! {
+            "id": id
+        })
+        .await?
+        .ok_or(Error::InvalidClient)?;
+    if let Some(client_secret) = &client.secret {
+        let Some(secret) = secret else {
+            return Err(Error::InvalidClient);
+        };
+        if hash_secret(&secret) != *client_secret {
+            return Err(Error::InvalidClient);
+        }
+    }
+    Ok(client)
+}
+
+async fn authorization_code(req: &HttpRequest, token: Token) -> Result<TokenResponse> {
+    let (client_id, client_secret) =
+        get_client_credentials(req, &token).ok_or(Error::InvalidClient)?;
+    let client = authenticate_client(client_id, client_secret).await?;
+    let code = token.code.ok_or(Error::InvalidGrant)?;
+    // codes are single use, even if the exchange fails
+    let authorization = PENDING_AUTHORIZATIONS
+        .complete(&code)
+        .await?
+        .ok_or(Error::InvalidGrant)?;
+    if authorization.client_id != client.id {
+        return Err(Error::InvalidGrant);
+    }
+    if token.redirect_uri.as_ref() != Some(&authorization.redirect_uri) {
+        return Err(Error::InvalidGrant);
+    }
+    if let Some(challenge) = &authorization.code_challenge {
+        let verifier = token.code_verifier.ok_or(Error::InvalidGrant)?;
+        if !verify_pkce(challenge, &authorization.code_challenge_method, &verifier) {
+            return Err(Error::InvalidGrant);
+        }
+    }
+    let now = get_time_secs();
+    let scope = authorization.scopes.join(" ");
+    let access_token = signing::encode(&AccessTokenClaims {
+        iss: PUBLIC_ROOT.to_string(),
+        sub: authorization.user_id.clone(),
+        aud: client.id.clone(),
+        exp: now + OAUTH_TOKEN_LIFETIME,
+        iat: now,
+        scope: scope.clone(),
+    })
+    .await?;
+    let id_token = signing::encode(&IdTokenClaims {
+        iss: PUBLIC_ROOT.to_string(),
+        sub: authorization.user_id.clone(),
+        aud: client.id,
+        exp: now + OAUTH_TOKEN_LIFETIME,
+        iat: now,
+        auth_time: authorization.auth_time,
+        nonce: authorization.nonce,
+        user: get_user_claims(&authorization.user_id, &authorization.scopes).await?,
+    })
+    .await?;
+    Ok(TokenResponse {
+        access_token,
+        token_type: "Bearer".to_string(),
+        expires_in: OAUTH_TOKEN_LIFETIME,
+        id_token: Some(id_token),
+        scope,
+    })
+}
+
+// machine clients authenticate with a secret or a private_key_jwt assertion
+async fn client_credentials(req: &HttpRequest, token: Token) -> Result<TokenResponse> {
+    let client = match &token.client_assertion_type {
+        Some(assertion_type) => {
+            if assertion_type != CLIENT_ASSERTION_TYPE {
+                return Err(Error::InvalidClient);
+            }
+            let assertion = token
+                .client_assertion
+                .as_ref()
+                .ok_or(Error::InvalidClient)?;
+            machine::authenticate_assertion(assertion).await?
+        }
+        None => {
+            let (client_id, client_secret) =
+                get_client_credentials(req, &token).ok_or(Error::InvalidClient)?;
+            let client_secret = client_secret.ok_or(Error::InvalidClient)?;
+            machine::authenticate_secret(&client_id, &client_secret).await?
+        }
+    };
+    let scopes = machine::grant_scopes(&client, token.scope.as_deref())?;
+    let scope = scopes
+        .iter()
+        .map(|scope| scope.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let access_token = machine::create_access_token(&client.id, scopes).await?;
+    Ok(TokenResponse {
+        access_token,
+        token_type: "Bearer".to_string(),
+        expires_in: MACHINE_TOKEN_LIFETIME,
+        id_token: None,
+        scope,
+    })
+}
+
+pub async fn handle(req: HttpRequest, token: web::Form<Token>) -> Result<impl Responder> {
+    let token = token.into_inner();
+    let response = match token.grant_type.as_str() {
+        "authorization_code" => authorization_code(&req, token).await?,
+        "client_credentials" => client_credentials(&req, token).await?,
+        _ => return Err(Error::UnsupportedGrantType),
+    };
+    Ok(web::Json(response))
+}
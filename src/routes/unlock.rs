@@ -0,0 +1,47 @@
+use actix_web::{web, HttpRequest, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::UNLOCK_TIMEOUT,
+    database::audit_event::{self, AuditEventKind},
+    errors::{Error, Result},
+    flows::Flow,
+    risk::clear_account_failures,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Unlock {
+    token: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnlockResponse {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PendingUnlock {
+    pub user_id: String,
+}
+
+pub static PENDING_UNLOCKS: Flow<PendingUnlock> = Flow::new("unlock", UNLOCK_TIMEOUT);
+
+// reached from the link in the email sent when password sign-ins are locked after
+// repeated failures; forgets the failures so the user can sign in straight away
+pub async fn handle(req: HttpRequest, unlock: web::Json<Unlock>) -> Result<impl Responder> {
+    let token = unlock.into_inner().token;
+    let Some(unlock) = PENDING_UNLOCKS.complete(&token).await? else {
+        return Err(Error::SessionExpired);
+    };
+    clear_account_failures(&unlock.user_id).await?;
+    audit_event::record(
+        &req,
+        AuditEventKind::SignInUnlocked,
+        Some(&unlock.user_id),
+        &unlock.user_id,
+        None,
+        None,
+    )
+    .await;
+    Ok(web::Json(UnlockResponse {}))
+}
@@ -1 +1,95 @@
-// TODO: wait until CDN is implemented
+use actix_web::{web, Responder};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    authenticate::Authenticate,
+    constants::{AVATAR_MAX_DIMENSION, AVATAR_MAX_SIZE},
+    database::{
+        event::{self, EventKind},
+        files::{File, FileMetadata},
+        profile,
+    },
+    errors::{Error, Result},
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateAvatar {
+    avatar: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateAvatarResponse {}
+
+// only a file that isn't in use elsewhere, as changing avatar later detaches the old file
+pub async fn get_avatar_file(id: &String) -> Result<File> {
+    let file = File::get(id).await.map_err(|_| Error::InvalidAvatar)?;
+    if file.attached || file.deleted || file.flagged {
+        return Err(Error::InvalidAvatar);
+    }
+    let FileMetadata::Image { width, height } = &file.metadata else {
+        return Err(Error::InvalidAvatar);
+    };
+    if file.size > AVATAR_MAX_SIZE
+        || *width > AVATAR_MAX_DIMENSION
+        || *height > AVATAR_MAX_DIMENSION
+    {
+        return Err(Error::AvatarTooLarge);
+    }
+    Ok(file)
+}
+
+// "default" and missing avatars have no CDN file
+pub async fn detach_avatar(avatar: Option<String>) -> Result<()> {
+    let Some(avatar) = avatar.filter(|a| a != "default") else {
+        return Ok(());
+    };
+    if let Ok(file) = File::get(&avatar).await {
+        file.detach().await?;
+    }
+    Ok(())
+}
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    update_avatar: web::Json<UpdateAvatar>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let avatar = update_avatar.into_inner().avatar;
+    let collection = profile::get_collection();
+    let profile = collection
+        .find_one(doc! {"id": &jwt.jwt_content.id})
+        .await?
+        .ok_or(Error::DatabaseError)?;
+    if profile.avatar.as_ref() == Some(&avatar) {
+        return Ok(web::Json(UpdateAvatarResponse {}));
+    }
+    let file = get_avatar_file(&avatar).await?;
+    file.attach().await?;
+    let result = collection
+        .update_one(
+            doc! {"id": &jwt.jwt_content.id},
+            doc! {
+                "$set": {
+                    "avatar": &file.id
+                }
+            },
+        )
+        .await;
+    // don't leave the file attached to a profile that doesn't use it
+    if let Err(error) = result {
+        file.detach().await.ok();
+        return Err(error.into());
+    }
+    detach_avatar(profile.avatar).await?;
+    event::publish(
+        EventKind::UserUpdated,
+        &jwt.jwt_content.id,
+        Some(json!({ "fields": ["avatar"] })),
+    )
+    .await;
+    Ok(web::Json(UpdateAvatarResponse {}))
+}
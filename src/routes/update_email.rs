@@ -1 +1,226 @@
-// TODO: validate email
+use actix_web::{web, HttpRequest, Responder};
+use async_std::task;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
+use mongodb::bson::{self, doc, Binary};
+use opaque_ke::{RegistrationRequest, RegistrationUpload};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    constants::SHORT_CONTINUE_TIMEOUT,
+    database::user,
+    environment::EMAIL_ENABLED,
+    errors::{Error, Result},
+    flows::Flow,
+    opaque::{begin_registration, finish_registration},
+    templates::resolve_locale,
+    utilities::{
+        generate_codes, generate_continue_token_long, send_email_change_in_use_email,
+        send_email_changed_email, send_update_email_code, validate_escalation, EMAIL_RE,
+    },
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE", tag = "stage")]
+pub enum UpdateEmail {
+    #[serde(rename_all = "camelCase")]
+    BeginUpdate {
+        // stage 1: send a code to the new address
+        escalation_token: String,
+        email: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    VerifyEmail {
+        // stage 2: code from the new address, password registration begin
+        // the password file is bound to the email, so it must be registered again
+        continue_token: String,
+        code: String,
+        // opaque data
+        message: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    FinishUpdate {
+        // stage 3: password registration
+        continue_token: String,
+        // opaque data 2
+        message: String,
+    },
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", untagged)]
+pub enum UpdateEmailResponse {
+    #[serde(rename_all = "camelCase")]
+    BeginUpdate {
+        continue_token: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    VerifyEmail {
+        continue_token: String,
+        message: String,
+    },
+    FinishUpdate {},
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PendingEmailUpdate {
+    pub user_id: String,
+    pub old_email: String,
+    pub email: String,
+    pub code: Option<String>,
+}
+
+pub static PENDING_EMAIL_UPDATES1: Flow<PendingEmailUpdate> =
+    Flow::new("update_email_code", SHORT_CONTINUE_TIMEOUT);
+pub static PENDING_EMAIL_UPDATES2: Flow<PendingEmailUpdate> =
+    Flow::new("update_email", SHORT_CONTINUE_TIMEOUT);
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    update_email: web::Json<UpdateEmail>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let update_email = update_email.into_inner();
+    match update_email {
+        UpdateEmail::BeginUpdate {
+            escalation_token,
+            email,
+        } => {
+            let user_id = validate_escalation(escalation_token, jwt.session_id).await?;
+            if !*EMAIL_ENABLED {
+                return Err(Error::EmailMisconfigured);
+            }
+            let email = email.trim().to_string();
+            if !EMAIL_RE.is_match(&email) {
+                return Err(Error::InvalidEmail);
+            }
+            let collection = user::get_collection();
+            let current = collection
+                .find_one(doc! {
+                    "id": &user_id
+                })
+                .await?
+                .ok_or(Error::DatabaseError)?;
+            let existing = collection
+                .find_one(doc! {
+                    "email": &email
+                })
+                .await?;
+            let continue_token = generate_continue_token_long();
+            // don't reveal whether the address is in use; its owner is told of the attempt instead
+            if existing.is_some() {
+                task::spawn(send_email_change_in_use_email(
+                    email,
+                    resolve_locale(current.locale.as_deref(), Some(&req)),
+                ));
+            } else {
+                let code = generate_codes().first().unwrap().to_string();
+                task::spawn(send_update_email_code(
+                    email.clone(),
+                    resolve_locale(current.locale.as_deref(), Some(&req)),
+                    code.clone(),
+                ));
+                PENDING_EMAIL_UPDATES1
+                    .insert(
+                        &continue_token,
+                        &PendingEmailUpdate {
+                            user_id,
+                            old_email: current.email,
+                            email,
+                            code: Some(code),
+                        },
+                    )
+                    .await?;
+            }
+            Ok(web::Json(UpdateEmailResponse::BeginUpdate {
+                continue_token,
+            }))
+        }
+        UpdateEmail::VerifyEmail {
+            continue_token,
+            code,
+            message,
+        } => {
+            let Some(session) = PENDING_EMAIL_UPDATES1.get(&continue_token).await? else {
+                return Err(Error::SessionExpired);
+            };
+            if session.user_id != jwt.jwt_content.id {
+                return Err(Error::UserMismatch);
+            }
+            if session.code.as_ref() != Some(&code) {
+                return Err(Error::IncorrectCode);
+            }
+            let result = begin_registration(
+                session.email.clone(),
+                RegistrationRequest::deserialize(&BASE64.decode(message)?)?,
+            )
+            .await?;
+            let new_continue_token = generate_continue_token_long();
+            PENDING_EMAIL_UPDATES2
+                .insert(
+                    &new_continue_token,
+                    &PendingEmailUpdate {
+                        user_id: session.user_id,
+                        old_email: session.old_email,
+                        email: session.email,
+                        code: None,
+                    },
+                )
+                .await?;
+            PENDING_EMAIL_UPDATES1.complete(&continue_token).await?;
+            Ok(web::Json(UpdateEmailResponse::VerifyEmail {
+                continue_token: new_continue_token,
+                message: BASE64.encode(result),
+            }))
+        }
+        UpdateEmail::FinishUpdate {
+            continue_token,
+            message,
+        } => {
+            let Some(session) = PENDING_EMAIL_UPDATES2.get(&continue_token).await? else {
+                return Err(Error::SessionExpired);
+            };
+            if session.user_id != jwt.jwt_content.id {
+                return Err(Error::UserMismatch);
+            }
+            let password_data =
+                finish_registration(RegistrationUpload::deserialize(&BASE64.decode(message)?)?)?;
+            let bin = Binary {
+                bytes: password_data,
+                subtype: bson::spec::BinarySubtype::Generic,
+            };
+            let collection = user::get_collection();
+            // the address may have been taken since the code was sent
+            let existing = collection
+                .find_one(doc! {
+                    "email": &session.email
+                })
+                .await?;
+            if existing.is_some() {
+                return Err(Error::UserExists);
+            }
+            let user = collection
+                .find_one_and_update(
+                    doc! {
+                        "id": &session.user_id
+                    },
+                    doc! {
+                        "$set": {
+                            "email": &session.email,
+                            "password_data": bin
+                        }
+                    },
+                )
+                .await?
+                .ok_or(Error::DatabaseError)?;
+            task::spawn(send_email_changed_email(
+                session.old_email,
+                resolve_locale(user.locale.as_deref(), Some(&req)),
+                session.email,
+            ));
+            PENDING_EMAIL_UPDATES2.complete(&continue_token).await?;
+            Ok(web::Json(UpdateEmailResponse::FinishUpdate {}))
+        }
+    }
+}
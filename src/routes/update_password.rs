@@ -1,16 +1,18 @@
-use actix_web::{web, Responder};
+use actix_web::{web, HttpRequest, Responder};
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
-use dashmap::DashMap;
-use lazy_static::lazy_static;
 use mongodb::bson::{self, doc, Binary};
 use opaque_ke::{RegistrationRequest, RegistrationUpload};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     authenticate::Authenticate,
+    constants::SHORT_CONTINUE_TIMEOUT,
+    database::audit_event::{self, AuditEventKind},
     errors::{Error, Result},
+    flows::Flow,
+    notifications::{notify, SecurityNotice},
     opaque::{begin_registration, finish_registration},
-    utilities::{generate_continue_token_long, get_time_secs, validate_escalation},
+    utilities::{generate_continue_token_long, validate_escalation},
 };
 
 #[derive(Deserialize, Serialize)]
@@ -39,16 +41,17 @@ pub enum UpdatePasswordResponse {
     FinishUpdate {},
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PendingUpdate {
-    pub time: u64,
+    pub user_id: String,
     pub email: String,
 }
 
-lazy_static! {
-    pub static ref PENDING_UPDATES: DashMap<String, PendingUpdate> = DashMap::new();
-}
+pub static PENDING_UPDATES: Flow<PendingUpdate> =
+    Flow::new("update_password", SHORT_CONTINUE_TIMEOUT);
 
 pub async fn handle(
+    req: HttpRequest,
     jwt: web::ReqData<Result<Authenticate>>,
     register: web::Json<UpdatePassword>,
 ) -> Result<impl Responder> {
@@ -59,7 +62,7 @@ pub async fn handle(
             escalation_token,
             message,
         } => {
-            validate_escalation(escalation_token, jwt.jwt).await?;
+            validate_escalation(escalation_token, jwt.session_id).await?;
             let user_collection = crate::database::user::get_collection();
             let user = user_collection
                 .find_one(doc! {
@@ -73,13 +76,15 @@ pub async fn handle(
             )
             .await?;
             let continue_token = generate_continue_token_long();
-            PENDING_UPDATES.insert(
-                continue_token.clone(),
-                PendingUpdate {
-                    time: get_time_secs(),
-                    email: user.email.clone(),
-                },
-            );
+            PENDING_UPDATES
+                .insert(
+                    &continue_token,
+                    &PendingUpdate {
+                        user_id: user.id.clone(),
+                        email: user.email.clone(),
+                    },
+                )
+                .await?;
             Ok(web::Json(UpdatePasswordResponse::BeginUpdate {
                 continue_token,
                 message: BASE64.encode(result),
@@ -89,11 +94,7 @@ pub async fn handle(
             message,
             continue_token,
         } => {
-            if let Some(session) = PENDING_UPDATES.get(&continue_token) {
-                if get_time_secs() - session.time > 600 {
-                    PENDING_UPDATES.remove(&continue_token);
-                    return Err(Error::SessionExpired);
-                }
+            if let Some(session) = PENDING_UPDATES.get(&continue_token).await? {
                 let password_data = finish_registration(RegistrationUpload::deserialize(
                     &BASE64.decode(message)?,
                 )?)?;
@@ -102,10 +103,10 @@ pub async fn handle(
                     bytes: password_data,
                 };
                 let user_collection = crate::database::user::get_collection();
-                user_collection
-                    .update_one(
+                let user = user_collection
+                    .find_one_and_update(
                         doc! {
-                            "id": session.email.clone()
+                            "id": &session.user_id
                         },
                         doc! {
                             "$set": {
@@ -113,8 +114,19 @@ pub async fn handle(
                             }
                         },
                     )
-                    .await?;
-                PENDING_UPDATES.remove(&continue_token);
+                    .await?
+                    .ok_or(Error::DatabaseError)?;
+                audit_event::record(
+                    &req,
+                    AuditEventKind::PasswordChanged,
+                    Some(&session.user_id),
+                    &session.user_id,
+                    Some(&jwt.session_id),
+                    None,
+                )
+                .await;
+                notify(&req, &user, SecurityNotice::PasswordChanged).await;
+                PENDING_UPDATES.complete(&continue_token).await?;
                 return Ok(web::Json(UpdatePasswordResponse::FinishUpdate {}));
             }
             Err(Error::InvalidToken)
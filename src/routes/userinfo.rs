@@ -0,0 +1,36 @@
+use actix_web::{web, HttpRequest, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::{Error, Result},
+    oidc::{get_user_claims, validate_access_token, UserClaims},
+};
+
+#[derive(Deserialize, Serialize)]
+pub struct UserinfoResponse {
+    sub: String,
+    #[serde(flatten)]
+    claims: UserClaims,
+}
+
+// access tokens issued to clients are not session tokens, so JwtMiddleware rejects them
+pub async fn handle(req: HttpRequest) -> Result<impl Responder> {
+    let token = req
+        .headers()
+        .get("Authorization")
+        .ok_or(Error::MissingToken)?
+        .to_str()
+        .map_err(|_| Error::InvalidToken)?
+        .strip_prefix("Bearer ")
+        .ok_or(Error::InvalidToken)?;
+    let claims = validate_access_token(token).await?;
+    let scopes = claims
+        .scope
+        .split_whitespace()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+    Ok(web::Json(UserinfoResponse {
+        claims: get_user_claims(&claims.sub, &scopes).await?,
+        sub: claims.sub,
+    }))
+}
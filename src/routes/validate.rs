@@ -17,18 +17,14 @@ pub struct ValidateResponse {
 }
 
 pub async fn handle(validate: web::Json<Validate>) -> Result<impl Responder> {
-    let token = validate_token(&validate.token).await;
-    if let Err(token) = token {
-        Err(token)
+    let token = validate_token(&validate.token).await?;
+    let Some(escalation) = &validate.escalation_token else {
+        return Ok(web::Json(ValidateResponse { escalated: false }));
+    };
+    let escalation = validate_escalation(escalation.to_string(), token.session_id).await;
+    if escalation.is_err() {
+        Ok(web::Json(ValidateResponse { escalated: false }))
     } else {
-        let Some(escalation) = &validate.escalation_token else {
-            return Ok(web::Json(ValidateResponse { escalated: false }));
-        };
-        let escalation = validate_escalation(escalation.to_string(), validate.token.clone()).await;
-        if escalation.is_err() {
-            Ok(web::Json(ValidateResponse { escalated: false }))
-        } else {
-            Ok(web::Json(ValidateResponse { escalated: true }))
-        }
+        Ok(web::Json(ValidateResponse { escalated: true }))
     }
 }
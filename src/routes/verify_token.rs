@@ -0,0 +1,35 @@
+use actix_web::{web, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::{validate_token, MachineAuthenticate},
+    database::machine_client::MachineScope,
+    errors::Result,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyToken {
+    token: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyTokenResponse {
+    user_id: String,
+    session_id: String,
+}
+
+// checked as the account service would check it, including the session still existing
+pub async fn handle(
+    jwt: web::ReqData<Result<MachineAuthenticate>>,
+    verify: web::Json<VerifyToken>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    jwt.require(MachineScope::TokensVerify)?;
+    let authenticate = validate_token(&verify.into_inner().token).await?;
+    Ok(web::Json(VerifyTokenResponse {
+        user_id: authenticate.jwt_content.id,
+        session_id: authenticate.session_id,
+    }))
+}
@@ -0,0 +1,355 @@
+use std::sync::RwLock;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
+use jsonwebtoken::{decode_header, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use lazy_static::lazy_static;
+use log::{error, info};
+use mongodb::bson::{doc, to_bson, Document};
+use ring::{
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::{
+    constants::{
+        SIGNING_KEY_CACHE_TIMEOUT, SIGNING_KEY_OVERLAP, SIGNING_KEY_PUBLISH_AHEAD,
+        SIGNING_KEY_RELOAD_INTERVAL, SIGNING_KEY_ROTATION,
+    },
+    database::settings::{self, get_settings, KeyAlgorithm, SigningKey},
+    encryption,
+    environment::JWT_ALGORITHM,
+    errors::{Error, Result},
+    utilities::get_time_secs,
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    pub x: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+    pub kid: String,
+    pub alg: String,
+    #[serde(rename = "use")]
+    pub key_use: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+lazy_static! {
+    // (time loaded, keys); refreshed periodically so rotations on other replicas are picked up
+    static ref KEY_CACHE: RwLock<Option<(u64, Vec<SigningKey>)>> = RwLock::new(None);
+}
+
+impl From<KeyAlgorithm> for Algorithm {
+    fn from(algorithm: KeyAlgorithm) -> Self {
+        match algorithm {
+            KeyAlgorithm::ES256 => Algorithm::ES256,
+            KeyAlgorithm::EdDSA => Algorithm::EdDSA,
+        }
+    }
+}
+
+impl SigningKey {
+    // can sign new tokens
+    fn is_active(&self, now: u64) -> bool {
+        self.retired_at.is_none() && self.activates_at <= now
+    }
+
+    fn is_pending(&self, now: u64) -> bool {
+        self.retired_at.is_none() && self.activates_at > now
+    }
+
+    fn can_verify(&self, now: u64) -> bool {
+        match self.retired_at {
+            Some(retired_at) => now.saturating_sub(retired_at) < SIGNING_KEY_OVERLAP,
+            None => true,
+        }
+    }
+
+    async fn encoding_key(&self) -> Result<EncodingKey> {
+        let private_key = BASE64
+            .decode(encryption::open(&self.private_key).await?)
+            .map_err(|_| Error::EncryptionError)?;
+        Ok(match self.algorithm {
+            KeyAlgorithm::ES256 => EncodingKey::from_ec_der(&private_key),
+            KeyAlgorithm::EdDSA => EncodingKey::from_ed_der(&private_key),
+        })
+    }
+
+    fn decoding_key(&self) -> DecodingKey {
+        match self.algorithm {
+            KeyAlgorithm::ES256 => DecodingKey::from_ec_der(&self.public_key),
+            KeyAlgorithm::EdDSA => DecodingKey::from_ed_der(&self.public_key),
+        }
+    }
+
+    fn to_jwk(&self) -> Jwk {
+        let (kty, crv, x, y) = match self.algorithm {
+            KeyAlgorithm::ES256 => (
+                "EC",
+                "P-256",
+                BASE64.encode(&self.public_key[1..33]),
+                Some(BASE64.encode(&self.public_key[33..65])),
+            ),
+            KeyAlgorithm::EdDSA => ("OKP", "Ed25519", BASE64.encode(&self.public_key), None),
+        };
+        Jwk {
+            kty: kty.to_string(),
+            crv: crv.to_string(),
+            x,
+            y,
+            kid: self.id.clone(),
+            alg: format!("{:?}", self.algorithm),
+            key_use: "sig".to_string(),
+        }
+    }
+}
+
+pub async fn create_signing_key() -> Result<SigningKey> {
+    let rng = SystemRandom::new();
+    let (private_key, public_key) = match *JWT_ALGORITHM {
+        KeyAlgorithm::ES256 => {
+            let document = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                .expect("Unexpected error: failed to generate signing key");
+            let key_pair =
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, document.as_ref(), &rng)
+                    .expect("Unexpected error: failed to parse signing key");
+            (
+                document.as_ref().to_vec(),
+                key_pair.public_key().as_ref().to_vec(),
+            )
+        }
+        KeyAlgorithm::EdDSA => {
+            let document = Ed25519KeyPair::generate_pkcs8(&rng)
+                .expect("Unexpected error: failed to generate signing key");
+            let key_pair = Ed25519KeyPair::from_pkcs8(document.as_ref())
+                .expect("Unexpected error: failed to parse signing key");
+            (
+                document.as_ref().to_vec(),
+                key_pair.public_key().as_ref().to_vec(),
+            )
+        }
+    };
+    Ok(SigningKey {
+        id: Ulid::new().to_string(),
+        algorithm: *JWT_ALGORITHM,
+        private_key: encryption::seal(&BASE64.encode(private_key)).await?,
+        public_key,
+        created_at: get_time_secs(),
+        activates_at: get_time_secs(),
+        retired_at: None,
+    })
+}
+
+async fn load_signing_keys() -> Result<Vec<SigningKey>> {
+    let settings = get_settings().await;
+    if !settings.signing_keys.is_empty() {
+        return Ok(settings.signing_keys);
+    }
+    // new settings, or settings created before signing keys existed
+    let key = to_bson(&create_signing_key().await?).map_err(|_| Error::DatabaseError)?;
+    settings::get_collection()
+        .update_one(
+            doc! {
+                "$or": [
+                    { "signing_keys": { "$exists": false } },
+                    { "signing_keys": { "$size": 0 } },
+                ]
+            },
+            doc! {
+                "$set": {
+                    "signing_keys": [key]
+                }
+            },
+        )
+        .await?;
+    Ok(get_settings().await.signing_keys)
+}
+
+async fn get_cached_keys(max_age: u64) -> Result<Vec<SigningKey>> {
+    let now = get_time_secs();
+    if let Some((time, keys)) = &*KEY_CACHE.read().expect("Unexpected error: lock poisoned") {
+        if now.saturating_sub(*time) < max_age {
+            return Ok(keys.clone());
+        }
+    }
+    let keys = load_signing_keys().await?;
+    *KEY_CACHE.write().expect("Unexpected error: lock poisoned") = Some((now, keys.clone()));
+    Ok(keys)
+}
+
+pub async fn get_signing_keys() -> Result<Vec<SigningKey>> {
+    get_cached_keys(SIGNING_KEY_CACHE_TIMEOUT).await
+}
+
+fn find_key(keys: &[SigningKey], kid: &str, now: u64) -> Option<SigningKey> {
+    keys.iter()
+        .find(|k| k.id == kid && k.can_verify(now))
+        .cloned()
+}
+
+pub async fn encode<T: Serialize>(claims: &T) -> Result<String> {
+    let keys = get_signing_keys().await?;
+    let now = get_time_secs();
+    let key = keys
+        .iter()
+        .filter(|k| k.is_active(now))
+        .max_by_key(|k| k.created_at)
+        .ok_or(Error::DatabaseError)?;
+    let mut header = Header::new(key.algorithm.into());
+    header.kid = Some(key.id.clone());
+    let token = jsonwebtoken::encode(&header, claims, &key.encoding_key().await?)
+        .expect("Unexpected error: failed to encode token");
+    Ok(token)
+}
+
+pub async fn decode<T: DeserializeOwned>(token: &str, mut validation: Validation) -> Result<T> {
+    let header = decode_header(token)?;
+    let kid = header.kid.ok_or(Error::InvalidToken)?;
+    let now = get_time_secs();
+    let key = match find_key(&get_signing_keys().await?, &kid, now) {
+        Some(key) => key,
+        // another replica may have started signing with a key this one hasn't loaded yet
+        None => find_key(
+            &get_cached_keys(SIGNING_KEY_RELOAD_INTERVAL).await?,
+            &kid,
+            now,
+        )
+        .ok_or(Error::InvalidToken)?,
+    };
+    validation.algorithms = vec![key.algorithm.into()];
+    let token_data = jsonwebtoken::decode::<T>(token, &key.decoding_key(), &validation)?;
+    Ok(token_data.claims)
+}
+
+pub async fn get_jwks() -> Result<JwkSet> {
+    let now = get_time_secs();
+    let keys = get_signing_keys()
+        .await?
+        .iter()
+        .filter(|k| k.can_verify(now))
+        .map(|k| k.to_jwk())
+        .collect();
+    Ok(JwkSet { keys })
+}
+
+async fn save_rotation(filter: Document, keys: &[SigningKey]) -> Result<bool> {
+    let keys = to_bson(keys).map_err(|_| Error::DatabaseError)?;
+    let result = settings::get_collection()
+        .update_one(
+            filter,
+            doc! {
+                "$set": {
+                    "signing_keys": keys
+                }
+            },
+        )
+        .await?;
+    if result.modified_count > 0 {
+        *KEY_CACHE.write().expect("Unexpected error: lock poisoned") = None;
+    }
+    Ok(result.modified_count > 0)
+}
+
+// A replacement key is published SIGNING_KEY_PUBLISH_AHEAD before it starts signing,
+// so that other replicas and relying parties caching the JWKS already know it. Once
+// it has taken over, the keys it replaced are retired.
+pub async fn rotate_keys() {
+    let keys = match load_signing_keys().await {
+        Ok(keys) => keys,
+        Err(_) => return,
+    };
+    let now = get_time_secs();
+    if keys.iter().any(|k| k.is_pending(now)) {
+        return;
+    }
+    let Some(active) = keys
+        .iter()
+        .filter(|k| k.is_active(now))
+        .max_by_key(|k| k.created_at)
+    else {
+        return;
+    };
+    let active_id = active.id.clone();
+    let replaced = keys
+        .iter()
+        .filter(|k| k.is_active(now) && k.id != active_id)
+        .map(|k| k.id.clone())
+        .collect::<Vec<_>>();
+    let result = if !replaced.is_empty() {
+        let retired = keys
+            .into_iter()
+            .filter(|k| k.can_verify(now))
+            .map(|mut k| {
+                if replaced.contains(&k.id) {
+                    k.retired_at = Some(now);
+                }
+                k
+            })
+            .collect::<Vec<_>>();
+        save_rotation(
+            doc! {
+                "signing_keys": {
+                    "$elemMatch": {
+                        "id": &replaced[0],
+                        "retired_at": null
+                    }
+                }
+            },
+            &retired,
+        )
+        .await
+        .map(|saved| saved.then(|| format!("Retired signing keys {}", replaced.join(", "))))
+    } else if now.saturating_sub(active.activates_at.max(active.created_at)) >= SIGNING_KEY_ROTATION
+    {
+        let mut next = match create_signing_key().await {
+            Ok(key) => key,
+            Err(e) => {
+                error!("Failed to create signing key: {:?}", e);
+                return;
+            }
+        };
+        next.activates_at = now + SIGNING_KEY_PUBLISH_AHEAD;
+        let next_id = next.id.clone();
+        let mut published = keys
+            .into_iter()
+            .filter(|k| k.can_verify(now))
+            .collect::<Vec<_>>();
+        published.push(next);
+        // only one replica wins if several try to rotate at once
+        save_rotation(
+            doc! {
+                "signing_keys": {
+                    "$elemMatch": {
+                        "id": &active_id,
+                        "retired_at": null
+                    },
+                    "$not": {
+                        "$elemMatch": {
+                            "activates_at": { "$gt": now as i64 }
+                        }
+                    }
+                }
+            },
+            &published,
+        )
+        .await
+        .map(|saved| {
+            saved.then(|| format!("Published signing key {} to replace {}", next_id, active_id))
+        })
+    } else {
+        return;
+    };
+    match result {
+        Ok(Some(message)) => info!("{}", message),
+        Ok(None) => {}
+        Err(e) => error!("Failed to rotate signing keys: {:?}", e),
+    }
+}
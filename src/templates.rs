@@ -0,0 +1,341 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use actix_web::HttpRequest;
+use handlebars::Handlebars;
+use log::{error, info};
+use once_cell::sync::OnceCell;
+use serde_json::{json, Value};
+
+use crate::{
+    environment::{EMAIL_TEMPLATES, EMAIL_TEMPLATE_OVERRIDES, PUBLIC_ROOT, SERVICE_NAME},
+    errors::{Error, Result},
+};
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+// every locale should provide a subject, text and HTML template for each of these
+pub const TEMPLATE_NAMES: [&str; 11] = [
+    "reset_password",
+    "verify_email",
+    "email_in_use",
+    "update_email_code",
+    "email_change_in_use",
+    "email_changed",
+    "recovery_codes_low",
+    "security_notice",
+    "sign_in_locked",
+    "deletion_scheduled",
+    "export_ready",
+];
+
+static TEMPLATES: OnceCell<Templates> = OnceCell::new();
+
+pub struct RenderedEmail {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+struct Templates {
+    html: Handlebars<'static>,
+    // subjects and plain-text bodies, which must not be HTML-escaped
+    text: Handlebars<'static>,
+    locales: Vec<String>,
+}
+
+// maps paths relative to the template directory to the file that provides them,
+// so a file in the overrides directory replaces the bundled file at the same path
+fn collect_files(root: &Path, dir: &Path, files: &mut BTreeMap<PathBuf, PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        error!("Failed to read email templates from {}", dir.display());
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(root, &path, files);
+        } else if path.extension().is_some_and(|ext| ext == "hbs") {
+            let relative = path
+                .strip_prefix(root)
+                .expect("Unexpected error: template outside of template directory")
+                .to_path_buf();
+            files.insert(relative, path);
+        }
+    }
+}
+
+fn load() -> Templates {
+    let mut files = BTreeMap::new();
+    let bundled = Path::new(&*EMAIL_TEMPLATES);
+    collect_files(bundled, bundled, &mut files);
+    if let Some(overrides) = &*EMAIL_TEMPLATE_OVERRIDES {
+        let overrides = Path::new(overrides);
+        collect_files(overrides, overrides, &mut files);
+    }
+
+    let mut html = Handlebars::new();
+    let mut text = Handlebars::new();
+    text.register_escape_fn(handlebars::no_escape);
+    let mut locales = Vec::new();
+    for (relative, path) in files {
+        let content = fs::read_to_string(&path)
+            .unwrap_or_else(|_| panic!("Failed to read email template {}", path.display()));
+        // e.g. "en/reset_password.html"
+        let name = relative
+            .with_extension("")
+            .to_string_lossy()
+            .replace('\\', "/");
+        let result = match name.split_once('/') {
+            // shared by every locale, e.g. layout.html
+            None => html.register_partial(name.trim_end_matches(".html"), content),
+            Some((locale, _)) => {
+                if !locales.iter().any(|l| l == locale) {
+                    locales.push(locale.to_string());
+                }
+                if name.ends_with(".html") {
+                    html.register_template_string(&name, content)
+                } else {
+                    text.register_template_string(&name, content)
+                }
+            }
+        };
+        result
+            .unwrap_or_else(|e| panic!("Failed to parse email template {}: {}", path.display(), e));
+    }
+    if !locales.iter().any(|l| l == DEFAULT_LOCALE) {
+        panic!(
+            "Email templates for the default locale ({}) are missing",
+            DEFAULT_LOCALE
+        );
+    }
+    info!("Loaded email templates for {}", locales.join(", "));
+    Templates {
+        html,
+        text,
+        locales,
+    }
+}
+
+// loads templates up front, so a broken template stops startup instead of an email
+pub fn init() {
+    get_templates();
+}
+
+fn get_templates() -> &'static Templates {
+    TEMPLATES.get_or_init(load)
+}
+
+pub fn get_locales() -> &'static [String] {
+    &get_templates().locales
+}
+
+// matches a language tag such as "fr-CA" against the available locales,
+// falling back to its primary language ("fr")
+fn match_locale(tag: &str) -> Option<String> {
+    let locales = get_locales();
+    let tag = tag.trim().to_lowercase();
+    let primary = tag.split('-').next().unwrap_or_default();
+    locales
+        .iter()
+        .find(|l| l.to_lowercase() == tag)
+        .or_else(|| locales.iter().find(|l| l.to_lowercase() == primary))
+        .cloned()
+}
+
+// language tags from an Accept-Language header, most preferred first
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut tags = header
+        .split(',')
+        .filter_map(|part| {
+            let mut pieces = part.trim().split(';');
+            let tag = pieces.next()?.trim();
+            if tag.is_empty() || tag == "*" {
+                return None;
+            }
+            let quality = pieces
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((tag.to_string(), quality))
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect::<Vec<_>>();
+    // stable, so equally weighted tags keep their order
+    tags.sort_by(|a, b| b.1.total_cmp(&a.1));
+    tags.into_iter().map(|(tag, _)| tag).collect()
+}
+
+pub fn get_accept_language(req: &HttpRequest) -> Vec<String> {
+    req.headers()
+        .get("Accept-Language")
+        .and_then(|header| header.to_str().ok())
+        .map(parse_accept_language)
+        .unwrap_or_default()
+}
+
+// prefers the locale stored on the user, then the request's Accept-Language header
+pub fn resolve_locale(user_locale: Option<&str>, req: Option<&HttpRequest>) -> String {
+    user_locale
+        .and_then(match_locale)
+        .or_else(|| {
+            req.map(get_accept_language)
+                .unwrap_or_default()
+                .iter()
+                .find_map(|tag| match_locale(tag))
+        })
+        .unwrap_or_else(|| DEFAULT_LOCALE.to_string())
+}
+
+pub fn render(name: &str, locale: &str, data: &Value) -> Result<RenderedEmail> {
+    let templates = get_templates();
+    // fall back per template, so a partially translated locale still works
+    let locale = if templates
+        .html
+        .has_template(&format!("{}/{}.html", locale, name))
+    {
+        locale
+    } else {
+        DEFAULT_LOCALE
+    };
+    let mut data = data.clone();
+    if let Some(object) = data.as_object_mut() {
+        object.insert("service_name".to_string(), json!(&*SERVICE_NAME));
+        object.insert("public_root".to_string(), json!(&*PUBLIC_ROOT));
+    }
+    let render = |registry: &Handlebars, kind: &str| {
+        registry
+            .render(&format!("{}/{}.{}", locale, name, kind), &data)
+            .map_err(|e| {
+                error!(
+                    "Failed to render email template {}/{}.{}: {}",
+                    locale, name, kind, e
+                );
+                Error::InternalEmailError
+            })
+    };
+    Ok(RenderedEmail {
+        subject: render(&templates.text, "subject")?.trim().to_string(),
+        text: render(&templates.text, "txt")?,
+        html: render(&templates.html, "html")?,
+    })
+}
+
+// example data for each template, keyed by the name of the preview file
+fn preview_samples() -> Vec<(String, &'static str, Value)> {
+    let root = &*PUBLIC_ROOT;
+    let mut samples = vec![
+        (
+            "reset_password".to_string(),
+            "reset_password",
+            json!({ "url": format!("{}/forgot?token=example", root) }),
+        ),
+        (
+            "verify_email".to_string(),
+            "verify_email",
+            json!({ "token": "12345678" }),
+        ),
+        ("email_in_use".to_string(), "email_in_use", json!({})),
+        (
+            "email_change_in_use".to_string(),
+            "email_change_in_use",
+            json!({}),
+        ),
+        (
+            "update_email_code".to_string(),
+            "update_email_code",
+            json!({ "token": "12345678" }),
+        ),
+        (
+            "email_changed".to_string(),
+            "email_changed",
+            json!({ "new_email": "new@example.com" }),
+        ),
+        (
+            "recovery_codes_low".to_string(),
+            "recovery_codes_low",
+            json!({ "remaining": 2 }),
+        ),
+        (
+            "sign_in_locked".to_string(),
+            "sign_in_locked",
+            json!({
+                "time": "2025-01-01T00:00:00Z",
+                "until": "2025-01-01T01:00:00Z",
+                "ip": "203.0.113.1",
+                "location": "Toronto, Canada",
+                "device": "Firefox 133.0 on Windows 10",
+                "unlock_url": format!("{}/unlock?token=example", root),
+            }),
+        ),
+        (
+            "deletion_scheduled".to_string(),
+            "deletion_scheduled",
+            json!({
+                "purge_at": "2025-01-15T00:00:00Z",
+                "cancel_url": format!("{}/restore?token=example", root),
+                "time": "2025-01-01T00:00:00Z",
+                "ip": "203.0.113.1",
+                "location": "Toronto, Canada",
+                "device": "Firefox 133.0 on Windows 10",
+                "lockdown_url": format!("{}/lockdown?token=example", root),
+            }),
+        ),
+        (
+            "export_ready".to_string(),
+            "export_ready",
+            json!({
+                "url": format!("{}/export?id=example", root),
+                "expires_at": "2025-01-08T00:00:00Z",
+            }),
+        ),
+    ];
+    for notice in [
+        "new_sign_in",
+        "password_changed",
+        "mfa_disabled",
+        "passkey_added",
+        "passkey_removed",
+        "username_changed",
+        "sessions_revoked",
+    ] {
+        samples.push((
+            format!("security_notice-{}", notice),
+            "security_notice",
+            json!({
+                "notice": notice,
+                "name": "YubiKey",
+                "username": "example",
+                "time": "2025-01-01T00:00:00Z",
+                "ip": "203.0.113.1",
+                "location": "Toronto, Canada",
+                "device": "Firefox 133.0 on Windows 10",
+                "lockdown_url": format!("{}/lockdown?token=example", root),
+            }),
+        ));
+    }
+    samples
+}
+
+// renders every template in every locale to files in `out`, without sending anything
+pub fn preview(out: &Path) -> Result<()> {
+    for locale in get_locales() {
+        let dir = out.join(locale);
+        fs::create_dir_all(&dir).map_err(|_| Error::InternalEmailError)?;
+        for (file, template, data) in preview_samples() {
+            let email = render(template, locale, &data)?;
+            fs::write(
+                dir.join(format!("{}.txt", file)),
+                format!("Subject: {}\n\n{}", email.subject, email.text),
+            )
+            .map_err(|_| Error::InternalEmailError)?;
+            fs::write(dir.join(format!("{}.html", file)), email.html)
+                .map_err(|_| Error::InternalEmailError)?;
+        }
+        info!("Rendered {} email previews to {}", locale, dir.display());
+    }
+    Ok(())
+}
@@ -6,52 +6,60 @@ use actix_extensible_rate_limit::{
     },
     HeaderCompatibleOutput, RateLimiter,
 };
-use actix_web::{dev::ServiceRequest, HttpResponse};
+use actix_web::{dev::ServiceRequest, HttpRequest, HttpResponse};
 use aes_gcm::{aead::Aead, Aes256Gcm, Nonce};
 use lazy_static::lazy_static;
-use lettre::{
-    message::header::ContentType, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
-    AsyncStd1Executor, AsyncTransport, Message,
-};
+use log::error;
 use mongodb::bson::doc;
 use rand::{distributions::Alphanumeric, rngs::StdRng, thread_rng, Rng, SeedableRng};
 use regex::Regex;
-use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
 
 use crate::{
-    database::session,
-    environment::{
-        HCAPTCHA_SECRET, PUBLIC_ROOT, SMTP_FROM, SMTP_PASSWORD, SMTP_SERVER, SMTP_USERNAME,
+    database::{
+        email_outbox, session,
+        user::{self, User},
     },
+    email,
+    environment::PUBLIC_ROOT,
     errors::Error,
     routes::login,
 };
 
 lazy_static! {
     pub static ref USERNAME_RE: Regex = Regex::new(r"^[0-9A-Za-z_.-]{3,32}$").expect("Unexpected error: failed to process regex");
+    // BCP 47 language tag, e.g. "en" or "pt-BR"
+    pub static ref LOCALE_RE: Regex = Regex::new(r"^[A-Za-z]{2,3}(-[A-Za-z0-9]{2,8})*$").expect("Unexpected error: failed to process regex");
     pub static ref EMAIL_RE: Regex = Regex::new(r#"^(?:[a-z0-9!#$%&'*+/=?^_`{|}~-]+(?:\.[a-z0-9!#$%&'*+/=?^_`{|}~-]+)*|"(?:[\x01-\x08\x0b\x0c\x0e-\x1f\x21\x23-\x5b\x5d-\x7f]|\\[\x01-\x09\x0b\x0c\x0e-\x7f])*")@(?:(?:[a-z0-9](?:[a-z0-9-]*[a-z0-9])?\.)+[a-z0-9](?:[a-z0-9-]*[a-z0-9])?|\[(?:(?:(2(5[0-5]|[0-4][0-9])|1[0-9][0-9]|[1-9]?[0-9]))\.){3}(?:(2(5[0-5]|[0-4][0-9])|1[0-9][0-9]|[1-9]?[0-9])|[a-z0-9-]*[a-z0-9]:(?:[\x01-\x08\x0b\x0c\x0e-\x1f\x21-\x5a\x53-\x7f]|\\[\x01-\x09\x0b\x0c\x0e-\x7f])+)\])$"#).expect("Unexpected error: failed to process regex");
 }
 
 pub fn encrypt(buffer: Vec<u8>, encrypt: Aes256Gcm) -> Vec<u8> {
     let mut rng = StdRng::from_entropy();
-    let mut nonce_bytes: Vec<u8> = vec![0; 96];
+    // 96-bit nonce
+    let mut nonce_bytes: Vec<u8> = vec![0; 12];
     rng.fill(&mut nonce_bytes[..]);
     let nonce = Nonce::from_slice(&nonce_bytes);
-    let mut encrypted = encrypt.encrypt(nonce, buffer.as_slice()).unwrap();
+    let mut encrypted = encrypt
+        .encrypt(nonce, buffer.as_slice())
+        .expect("Unexpected error: failed to encrypt");
     let mut result = Vec::new();
     result.append(&mut nonce_bytes);
     result.append(&mut encrypted);
     result
 }
 
-pub fn decrypt(mut buffer: Vec<u8>, encrypt: Option<Aes256Gcm>) -> Vec<u8> {
+pub fn decrypt(mut buffer: Vec<u8>, encrypt: Option<Aes256Gcm>) -> crate::errors::Result<Vec<u8>> {
     if let Some(e) = encrypt {
-        let data = buffer.split_off(96);
+        if buffer.len() < 12 {
+            return Err(Error::EncryptionError);
+        }
+        let data = buffer.split_off(12);
         let nonce = Nonce::from_slice(&buffer);
-        let decrypted = e.decrypt(nonce, data.as_slice()).unwrap();
-        decrypted
+        e.decrypt(nonce, data.as_slice())
+            .map_err(|_| Error::EncryptionError)
     } else {
-        buffer
+        Ok(buffer)
     }
 }
 
@@ -130,99 +138,124 @@ pub fn generate_continue_token_long() -> String {
         .collect()
 }
 
-pub async fn send_email(to: String, subject: String, body: String) -> crate::errors::Result<()> {
-    let Some(from) = &*SMTP_FROM else {
-        return Err(Error::EmailMisconfigured);
-    };
-    let Some(username) = &*SMTP_USERNAME else {
-        return Err(Error::EmailMisconfigured);
-    };
-    let Some(password) = &*SMTP_PASSWORD else {
-        return Err(Error::EmailMisconfigured);
-    };
-    let Some(server) = &*SMTP_SERVER else {
+pub fn hash_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+// queues the email in the outbox, see email::deliver_queued
+async fn queue_email(
+    to: String,
+    template: &str,
+    locale: String,
+    data: serde_json::Value,
+    dedupe_key: Option<String>,
+) -> crate::errors::Result<()> {
+    if email::get_transport().is_none() {
         return Err(Error::EmailMisconfigured);
-    };
-    let email = Message::builder()
-        .from(from.parse().map_err(|_| Error::EmailMisconfigured)?)
-        .to(to.parse().map_err(|_| Error::InternalEmailError)?)
-        .subject(subject)
-        .header(ContentType::TEXT_PLAIN)
-        .body(body)
-        .map_err(|_| Error::EmailMisconfigured)?;
-    let creds = Credentials::new(username.to_string(), password.to_string());
-    let mailer = AsyncSmtpTransport::<AsyncStd1Executor>::relay(server)
-        .expect("failed to set server")
-        .credentials(creds)
-        .build();
-    mailer
-        .send(email)
-        .await
-        .map_err(|_| Error::InternalEmailError)?;
-    Ok(())
+    }
+    let result = email_outbox::enqueue(to, template, locale, data, dedupe_key).await;
+    // callers usually spawn this and drop the result
+    if let Err(e) = &result {
+        error!("Failed to queue {} email: {:?}", template, e);
+    }
+    result
 }
 
-pub async fn send_reset_email(to: String, token: String) -> crate::errors::Result<()> {
+pub async fn send_template_email(
+    to: String,
+    template: &str,
+    locale: String,
+    data: serde_json::Value,
+) -> crate::errors::Result<()> {
+    queue_email(to, template, locale, data, None).await
+}
+
+pub async fn send_reset_email(
+    to: String,
+    locale: String,
+    token: String,
+) -> crate::errors::Result<()> {
     let continue_url = format!("{}/forgot?token={}", &*PUBLIC_ROOT, token);
-    send_email(to, "Reset password".to_string(), format!("Hi there! We received a request to reset your password. If this was you, please click the following link to continue.\n\n{}", continue_url)).await
+    // repeated requests replace the queued email, so only the newest link is sent
+    let dedupe_key = format!("reset_password:{}", to);
+    queue_email(
+        to,
+        "reset_password",
+        locale,
+        json!({ "url": continue_url }),
+        Some(dedupe_key),
+    )
+    .await
 }
 
-pub async fn send_verify_email(to: String, token: String) -> crate::errors::Result<()> {
-    send_email(to, "Verify email".to_string(), format!("Hi there! We received a request to create an account. If this was you, please enter the following token to continue.\n\n{}", token)).await
+pub async fn send_verify_email(
+    to: String,
+    locale: String,
+    token: String,
+) -> crate::errors::Result<()> {
+    send_template_email(to, "verify_email", locale, json!({ "token": token })).await
 }
 
-pub async fn send_in_use_email(to: String) -> crate::errors::Result<()> {
-    send_email(to, "Verify email".to_string(), "Hi there! We received a request to create an account. However, this email is already in use. If this was you, please reset your password instead.".to_string()).await
+pub async fn send_in_use_email(to: String, locale: String) -> crate::errors::Result<()> {
+    send_template_email(to, "email_in_use", locale, json!({})).await
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct HCaptchaResponse {
-    success: bool,
-    challenge_ts: Option<String>,
-    hostname: Option<String>,
-    credit: Option<bool>,
-    error_codes: Option<Vec<String>>,
+pub async fn send_email_change_in_use_email(
+    to: String,
+    locale: String,
+) -> crate::errors::Result<()> {
+    send_template_email(to, "email_change_in_use", locale, json!({})).await
 }
 
-pub async fn validate_captcha(token: String) -> crate::errors::Result<()> {
-    let client = reqwest::Client::new();
-    let result = client
-        .post("https://hcaptcha.com/siteverify")
-        .query(&[("response", token), ("secret", HCAPTCHA_SECRET.to_string())])
-        .send()
-        .await;
-    let Ok(result) = result else {
-        return Err(Error::InternalCaptchaError);
-    };
-    if result.status() != reqwest::StatusCode::OK {
-        return Err(Error::InternalCaptchaError);
-    }
-    let text = result
-        .text()
-        .await
-        .expect("Unexpected error: failed to read response");
-    let response: HCaptchaResponse = serde_json::from_str(&text)
-        .expect("Unexpected error: failed to convert response into JSON");
-    if !response.success {
-        return Err(Error::InvalidCaptcha);
-    }
-    Ok(())
+pub async fn send_update_email_code(
+    to: String,
+    locale: String,
+    token: String,
+) -> crate::errors::Result<()> {
+    send_template_email(to, "update_email_code", locale, json!({ "token": token })).await
+}
+
+pub async fn send_email_changed_email(
+    to: String,
+    locale: String,
+    new_email: String,
+) -> crate::errors::Result<()> {
+    send_template_email(
+        to,
+        "email_changed",
+        locale,
+        json!({ "new_email": new_email }),
+    )
+    .await
+}
+
+pub async fn send_codes_low_email(
+    to: String,
+    locale: String,
+    remaining: u64,
+) -> crate::errors::Result<()> {
+    send_template_email(
+        to,
+        "recovery_codes_low",
+        locale,
+        json!({ "remaining": remaining }),
+    )
+    .await
 }
 
 pub async fn validate_escalation(
     escalation_token: String,
-    token: String,
+    session_id: String,
 ) -> crate::errors::Result<String> {
-    let escalate = login::ACTIVE_ESCALATIONS.get(&escalation_token);
+    let escalate = login::ACTIVE_ESCALATIONS.get(&escalation_token).await?;
     let Some(escalate) = escalate else {
         return Err(Error::SessionExpired);
     };
-    if get_time_secs() - escalate.time > 3600 {
-        drop(escalate);
-        login::ACTIVE_ESCALATIONS.remove(&escalation_token);
+
+    if session_id != escalate.session_id {
         return Err(Error::SessionExpired);
     }
-
+    // the session may have been logged out since escalating
     let sessions = session::get_collection();
     let session = sessions
         .find_one(doc! {"id": escalate.session_id.clone()})
@@ -231,17 +264,32 @@ pub async fn validate_escalation(
         return Err(Error::SessionExpired);
     }
 
-    let user_session = sessions.find_one(doc! { "token": token }).await?;
-    if user_session.is_none() {
-        return Err(Error::SessionExpired);
-    }
-    if user_session.unwrap().id != escalate.session_id {
-        return Err(Error::SessionExpired);
-    }
-
     Ok(escalate.user_id.clone())
 }
 
+// admin routes take the escalation token in a header, since most of them have no body
+pub async fn validate_administrator(
+    req: &HttpRequest,
+    session_id: String,
+) -> crate::errors::Result<User> {
+    let escalation_token = req
+        .headers()
+        .get("X-Escalation-Token")
+        .and_then(|token| token.to_str().ok())
+        .ok_or(Error::MissingToken)?;
+    let user_id = validate_escalation(escalation_token.to_string(), session_id).await?;
+    let admin = user::get_collection()
+        .find_one(doc! {
+            "id": &user_id
+        })
+        .await?
+        .ok_or(Error::DatabaseError)?;
+    if !admin.platform_administrator {
+        return Err(Error::MissingPermission);
+    }
+    Ok(admin)
+}
+
 pub fn get_time() -> Duration {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
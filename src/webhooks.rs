@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use log::{error, warn};
+use mongodb::bson::doc;
+use reqwest::{header::CONTENT_TYPE, redirect::Policy, Client};
+use ring::hmac;
+
+use crate::{
+    constants::{WEBHOOK_MAX_ATTEMPTS, WEBHOOK_RETRY_BASE, WEBHOOK_RETRY_MAX, WEBHOOK_TIMEOUT},
+    database::{
+        webhook::{self, Webhook},
+        webhook_delivery::{self, WebhookDelivery},
+    },
+    encryption,
+    utilities::get_time_secs,
+};
+
+// Other services subscribe to account events (see database::event::EventKind) by
+// registering a webhook. Every event is queued for each subscribed webhook in the
+// same transaction as the event, then POSTed by deliver_queued with an
+// X-Webhook-Signature header of `t=<timestamp>,v1=<HMAC-SHA256 of "<timestamp>.<body>">`
+// keyed with the webhook's secret. Failed deliveries are retried with exponential
+// backoff, and every attempt is kept in the webhook's delivery log.
+
+lazy_static! {
+    static ref CLIENT: Client = Client::builder()
+        .timeout(Duration::from_secs(WEBHOOK_TIMEOUT))
+        // a redirect would send the payload somewhere that wasn't registered
+        .redirect(Policy::none())
+        .build()
+        .expect("Failed to create webhook client");
+}
+
+struct DeliveryError {
+    // none if the endpoint couldn't be reached
+    response_status: Option<u16>,
+    message: String,
+    // retrying won't help, e.g. the webhook was deleted
+    permanent: bool,
+}
+
+impl DeliveryError {
+    fn transient(response_status: Option<u16>, message: String) -> Self {
+        DeliveryError {
+            response_status,
+            message,
+            permanent: false,
+        }
+    }
+
+    fn permanent(message: String) -> Self {
+        DeliveryError {
+            response_status: None,
+            message,
+            permanent: true,
+        }
+    }
+}
+
+pub fn sign(secret: &str, timestamp: u64, payload: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, format!("{}.{}", timestamp, payload).as_bytes());
+    let signature = tag
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    format!("t={},v1={}", timestamp, signature)
+}
+
+async fn deliver(webhook: &Webhook, delivery: &WebhookDelivery) -> Result<u16, DeliveryError> {
+    let secret = encryption::open(&webhook.secret)
+        .await
+        .map_err(|e| DeliveryError::transient(None, format!("secret: {:?}", e)))?;
+    let response = CLIENT
+        .post(&webhook.url)
+        .header(CONTENT_TYPE, "application/json")
+        .header("X-Webhook-Id", &webhook.id)
+        .header("X-Webhook-Delivery", &delivery.id)
+        .header(
+            "X-Webhook-Signature",
+            sign(&secret, get_time_secs(), &delivery.payload),
+        )
+        .body(delivery.payload.clone())
+        .send()
+        .await
+        .map_err(|e| DeliveryError::transient(None, format!("request: {}", e)))?;
+    let status = response.status();
+    if status.is_success() {
+        Ok(status.as_u16())
+    } else {
+        Err(DeliveryError::transient(
+            Some(status.as_u16()),
+            format!("endpoint responded with {}", status),
+        ))
+    }
+}
+
+async fn attempt(delivery: &WebhookDelivery) -> Result<u16, DeliveryError> {
+    let webhook = webhook::get_collection()
+        .find_one(doc! {
+            "id": &delivery.webhook_id
+        })
+        .await
+        .map_err(|e| DeliveryError::transient(None, format!("database: {}", e)))?
+        .ok_or_else(|| DeliveryError::permanent("webhook was deleted".to_string()))?;
+    deliver(&webhook, delivery).await
+}
+
+fn get_retry_delay(attempts: u32) -> u64 {
+    WEBHOOK_RETRY_BASE
+        .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+        .min(WEBHOOK_RETRY_MAX)
+}
+
+// sends every webhook delivery that is due
+pub async fn deliver_queued() {
+    loop {
+        let delivery = match webhook_delivery::claim().await {
+            Ok(Some(delivery)) => delivery,
+            Ok(None) => break,
+            Err(e) => {
+                error!("Failed to read webhook deliveries: {:?}", e);
+                break;
+            }
+        };
+        let result = match attempt(&delivery).await {
+            Ok(status) => webhook_delivery::mark_delivered(&delivery.id, status).await,
+            Err(e) => {
+                let retry_at = if e.permanent || delivery.attempts >= WEBHOOK_MAX_ATTEMPTS {
+                    error!(
+                        "Giving up on webhook delivery {} after {} attempts: {}",
+                        delivery.id, delivery.attempts, e.message
+                    );
+                    None
+                } else {
+                    warn!(
+                        "Failed to deliver webhook {}, retrying: {}",
+                        delivery.id, e.message
+                    );
+                    Some(get_time_secs() + get_retry_delay(delivery.attempts))
+                };
+                webhook_delivery::mark_failed(&delivery.id, e.response_status, e.message, retry_at)
+                    .await
+            }
+        };
+        if let Err(e) = result {
+            error!("Failed to update webhook delivery {}: {:?}", delivery.id, e);
+        }
+    }
+}